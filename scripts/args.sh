@@ -0,0 +1,5 @@
+echo $0 $# "$@"
+shift
+echo $# $1
+shift 2
+echo $# "$*"
@@ -0,0 +1,2 @@
+LIB="lib $#"
+set -- sourced
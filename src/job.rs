@@ -4,37 +4,101 @@
 //! a *job*. This helps manage the commands the shell runs.
 
 use std::{
+    env,
+    io,
+    fs,
     borrow::Cow,
     process::exit,
     ffi::CString,
     cell::RefCell,
     rc::Rc,
-    os::unix::io::RawFd,
+    os::unix::{ffi::OsStrExt, io::RawFd},
+    path::PathBuf,
 };
 use nix::{
-    unistd::{self, execvp, dup2, close, Pid, ForkResult},
+    errno::Errno,
+    unistd::{self, execv, dup2, close, Pid, ForkResult},
     sys::wait::{waitpid, WaitStatus, WaitPidFlag},
 };
+use crate::lookup;
 
 #[derive(Debug, Copy, Clone)]
 pub struct IO(pub [RawFd; 3]);
 
 impl IO {
-    fn dup(&self) -> Result<(), nix::Error> {
-        if self.0[0] != 0 {
-            dup2(self.0[0], 0)?;
-            close(self.0[0])?;
+    /// Replace the standard descriptors of this process with these ones,
+    /// closing any which are `-1`.
+    ///
+    /// The descriptors above 2 stay open, they're still owned by whoever
+    /// opened them, see `IO::dup_and_close`.
+    pub fn dup(&self) -> Result<(), nix::Error> {
+        // Copy every descriptor first, since one may be the source of another,
+        // like `2>&1 >file`.
+        let mut copies = [-1; 3];
+        for (n, fd) in self.0.iter().enumerate() {
+            if *fd >= 0 && *fd != n as RawFd {
+                copies[n] = unistd::dup(*fd)?;
+            }
         }
-        if self.0[1] != 1 {
-            dup2(self.0[1], 1)?;
-            close(self.0[1])?;
+        for (n, fd) in self.0.iter().enumerate() {
+            if *fd < 0 {
+                close(n as RawFd).ok();
+            } else if *fd != n as RawFd {
+                dup2(copies[n], n as RawFd)?;
+                close(copies[n])?;
+            }
         }
-        if self.0[2] != 2 {
-            dup2(self.0[2], 2)?;
-            close(self.0[2])?;
+        Ok(())
+    }
+
+    /// Replace the standard descriptors like `IO::dup`, then close the
+    /// descriptors above 2, taking ownership of them. Only a process which
+    /// is about to run a command, and won't return to its caller, does this.
+    pub fn dup_and_close(&self) -> Result<(), nix::Error> {
+        self.dup()?;
+        for fd in self.0.iter().filter(|fd| **fd > 2) {
+            close(*fd).ok();
         }
         Ok(())
     }
+
+    /// A reader for the standard input, for builtins which read it from
+    /// the shell's own process.
+    pub fn stdin(&self) -> Fd {
+        Fd(self.0[0])
+    }
+
+    /// A writer for the standard output, see `IO::stdin`.
+    pub fn stdout(&self) -> Fd {
+        Fd(self.0[1])
+    }
+
+    /// A writer for the standard error, see `IO::stdout`.
+    pub fn stderr(&self) -> Fd {
+        Fd(self.0[2])
+    }
+}
+
+/// A borrowed file descriptor, which is never closed when dropped.
+#[derive(Debug, Copy, Clone)]
+pub struct Fd(pub RawFd);
+
+impl io::Read for Fd {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        unistd::read(self.0, buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+impl io::Write for Fd {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        unistd::write(self.0, buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 impl Default for IO {
@@ -59,8 +123,11 @@ impl Default for IO {
 /// exactly **one** Job each.
 pub struct Job {
     argv: Vec<CString>,
+    env: Vec<(String, String)>,
     // TODO: Call this pid?
     child: Option<Pid>,
+    // Temporary directories of the command, removed along with the job.
+    temporary: Vec<PathBuf>,
 }
 
 impl Job {
@@ -69,10 +136,24 @@ impl Job {
     pub fn new(argv: Vec<CString>) -> Self {
         Job {
             argv,
+            env: vec![],
             child: None,
+            temporary: vec![],
         }
     }
 
+    /// Remove a temporary directory once the job is dropped, which is after
+    /// it finishes for a job in the foreground, or once a background job is
+    /// reported as done.
+    pub fn temporary(&mut self, directory: PathBuf) {
+        self.temporary.push(directory);
+    }
+
+    /// Set an environment variable for only this job's command.
+    pub fn env(&mut self, name: &str, value: &str) {
+        self.env.push((name.into(), value.into()));
+    }
+
     pub fn body(&self) -> String {
         self.argv.iter().map(|a| {
             a.to_string_lossy()
@@ -94,17 +175,18 @@ impl Job {
 
     /// Run a shell job in the background.
     pub fn fork(&mut self, io: IO) -> nix::Result<WaitStatus> {
+        self.find();
         match unistd::fork() {
             Ok(ForkResult::Parent { child, .. }) => {
                 self.child = Some(child);
                 self.status()
             },
             Ok(ForkResult::Child) => {
-                io.dup()?;
+                io.dup_and_close()?;
                 // TODO #20: When running with raw mode we could buffer
                 // this and print it later, all at once in suspended raw mode.
-                if let Err(_) = self.exec() {
-                    exit(127);
+                if let Err(error) = self.exec() {
+                    exit(self.failed(error));
                 } else {
                     self.status()
                 }
@@ -115,15 +197,16 @@ impl Job {
 
     /// Run a shell job, waiting for the command to finish.
     pub fn fork_and_wait(&mut self, io: IO) -> nix::Result<WaitStatus> {
+        self.find();
         match unistd::fork() {
             Ok(ForkResult::Parent { child, .. }) => {
                 self.child = Some(child);
                 self.wait()
             },
             Ok(ForkResult::Child) => {
-                io.dup()?;
-                if let Err(_) = self.exec() {
-                    exit(127);
+                io.dup_and_close()?;
+                if let Err(error) = self.exec() {
+                    exit(self.failed(error));
                 } else {
                     // TODO: Waiting in the child?
                     unimplemented!();
@@ -133,10 +216,64 @@ impl Job {
         }
     }
 
-    fn exec(&self) -> Result<(), nix::Error> {
-        execvp(&self.argv[0], &self.argv.iter()
-                                        .map(|a| a.as_c_str())
-                                        .collect::<Vec<_>>()[..]).map(|_| ())
+    /// Replace the current process with the job's command, only returning
+    /// on failure.
+    ///
+    /// The command is found in the `$PATH` with `lookup::find`, so its
+    /// location is remembered. Like `execvp`, a file which is found but
+    /// isn't executable fails with `EACCES`, and a file without a `#!` line
+    /// is run as a script by `/bin/sh`.
+    pub fn exec(&self) -> Result<(), nix::Error> {
+        for (name, value) in &self.env {
+            env::set_var(name, value);
+        }
+        let name = self.argv[0].to_string_lossy();
+        let path = match lookup::find(&name) {
+            Some(path) => path,
+            // `execv` itself finds why the file can't be run.
+            None if name.contains('/') => PathBuf::from(&*name),
+            None => {
+                let found = env::var_os("PATH").map_or(false, |path| {
+                    env::split_paths(&path).any(|dir| dir.join(&*name).is_file())
+                });
+                let errno = if found { Errno::EACCES } else { Errno::ENOENT };
+                return Err(nix::Error::Sys(errno));
+            },
+        };
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| nix::Error::InvalidPath)?;
+        let argv: Vec<_> = self.argv.iter().map(|a| a.as_c_str()).collect();
+        // TODO: Commands should start with the default `SIGPIPE` action,
+        // which Rust ignores, but it can't be restored without unsafe code.
+        match execv(&path, &argv) {
+            Err(nix::Error::Sys(Errno::ENOEXEC)) => {
+                let sh = CString::new("/bin/sh").expect("error in path");
+                let mut script = vec![sh.as_c_str(), path.as_c_str()];
+                script.extend_from_slice(&argv[1..]);
+                execv(&sh, &script).map(|_| ())
+            },
+            result => result.map(|_| ()),
+        }
+    }
+
+    // Report why the command of a child couldn't be run, returning its
+    // status, see `status`.
+    fn failed(&self, error: nix::Error) -> i32 {
+        let code = status(&error);
+        // The shell itself reports commands which aren't found once it
+        // waits for them.
+        if code != 127 {
+            eprintln!("oursh: {}: {}", self.argv[0].to_string_lossy(), error);
+        }
+        code
+    }
+
+    // Find the command before forking, so the shell itself remembers where
+    // it is. A job with its own `$PATH` is only searched for in the child.
+    fn find(&self) {
+        if !self.env.iter().any(|(name, _)| name == "PATH") {
+            lookup::find(&self.argv[0].to_string_lossy());
+        }
     }
 
     fn wait(&mut self) -> nix::Result<WaitStatus> {
@@ -160,4 +297,21 @@ impl Job {
     }
 }
 
+/// The status of a command which couldn't be run because of an `exec`
+/// error, 127 when it wasn't found and 126 when it couldn't be executed.
+pub fn status(error: &nix::Error) -> i32 {
+    match error {
+        nix::Error::Sys(Errno::ENOENT) => 127,
+        _ => 126,
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        for directory in &self.temporary {
+            fs::remove_dir_all(directory).ok();
+        }
+    }
+}
+
 pub type Jobs = Rc<RefCell<Vec<(String, Job)>>>;
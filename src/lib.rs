@@ -85,6 +85,7 @@
 
 extern crate nix;
 extern crate pwd;
+extern crate serde_json;
 extern crate termion;
 
 #[macro_use]
@@ -117,6 +118,7 @@ macro_rules! debug {
 }
 
 pub mod job;
+pub mod lookup;
 pub mod program;
 pub mod repl;
 
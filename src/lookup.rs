@@ -0,0 +1,172 @@
+//! Finding the executables of commands in the `$PATH`.
+//!
+//! Searching every directory of the `$PATH` for each command is slow, so the
+//! location of each command is remembered in a hash table, like other shells
+//! do (see the `hash` builtin). The table is forgotten whenever `$PATH`
+//! changes, and a remembered location which is no longer executable is
+//! searched for again.
+//!
+//! The names of every executable, for completion, are remembered for each
+//! directory until the directory is modified.
+//!
+//! ```
+//! use oursh::lookup;
+//!
+//! let sh = lookup::find("sh").unwrap();
+//! assert!(sh.is_absolute());
+//! assert_eq!(Some(sh), lookup::hashed("sh"));
+//! assert!(lookup::executables().contains(&"sh".into()));
+//! ```
+
+use std::{
+    env,
+    fs,
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// The `$PATH` of `command -p`, where the standard utilities are found.
+pub const DEFAULT_PATH: &str = "/usr/bin:/bin";
+
+#[derive(Default)]
+struct Table {
+    // The `$PATH` which the commands were found in.
+    path: Option<OsString>,
+    // The location of each command found.
+    commands: HashMap<String, PathBuf>,
+    // The executables in each directory, with the time it was modified when
+    // they were read.
+    directories: HashMap<PathBuf, (SystemTime, Vec<String>)>,
+}
+
+thread_local! {
+    static TABLE: RefCell<Table> = RefCell::new(Table::default());
+}
+
+// Use the table, first forgetting every command if `$PATH` has changed.
+fn with<T, F: FnOnce(&mut Table) -> T>(f: F) -> T {
+    TABLE.with(|table| {
+        let mut table = table.borrow_mut();
+        let path = env::var_os("PATH");
+        if table.path != path {
+            table.commands.clear();
+            table.path = path;
+        }
+        f(&mut table)
+    })
+}
+
+/// Find the executable of a command, remembering where it was found.
+///
+/// Names containing a `/` are never searched for in the `$PATH`.
+pub fn find(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name)).filter(|p| is_executable(p));
+    }
+
+    with(|table| {
+        match table.commands.get(name) {
+            Some(path) if is_executable(path) => return Some(path.clone()),
+            _ => {},
+        }
+        let path = search(name, table.path.as_ref()?)?;
+        table.commands.insert(name.into(), path.clone());
+        Some(path)
+    })
+}
+
+/// Search for the executable of a command in the given `$PATH`, without
+/// remembering it.
+pub fn search(name: &str, path: &OsStr) -> Option<PathBuf> {
+    env::split_paths(path).map(|dir| {
+        // An empty directory is the current directory.
+        if dir.as_os_str().is_empty() { PathBuf::from(".") } else { dir }
+    }).map(|dir| dir.join(name)).find(|file| is_executable(file))
+}
+
+/// The remembered location of a command, without searching for it.
+pub fn hashed(name: &str) -> Option<PathBuf> {
+    with(|table| table.commands.get(name).cloned())
+}
+
+/// Every remembered command and its location, sorted by name.
+pub fn remembered() -> Vec<(String, PathBuf)> {
+    let mut commands: Vec<_> = with(|table| {
+        table.commands.iter().map(|(n, p)| (n.clone(), p.clone())).collect()
+    });
+    commands.sort();
+    commands
+}
+
+/// Forget the location of every command.
+pub fn forget() {
+    with(|table| table.commands.clear());
+}
+
+/// The names of every executable in the `$PATH`.
+pub fn executables() -> Vec<String> {
+    with(|table| {
+        let path = match table.path.clone() {
+            Some(path) => path,
+            None => return vec![],
+        };
+
+        let mut names = vec![];
+        for dir in env::split_paths(&path) {
+            let modified = match fs::metadata(&dir).and_then(|m| m.modified()) {
+                Ok(modified) => modified,
+                Err(_) => continue,
+            };
+            match table.directories.get(&dir) {
+                Some((time, cached)) if *time == modified => {
+                    names.extend(cached.iter().cloned());
+                },
+                _ => {
+                    let read = read_executables(&dir);
+                    names.extend(read.iter().cloned());
+                    table.directories.insert(dir, (modified, read));
+                },
+            }
+        }
+        names
+    })
+}
+
+fn read_executables(dir: &Path) -> Vec<String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries.filter_map(|e| e.ok()).filter(|e| is_executable(&e.path()))
+           .map(|e| e.file_name().to_string_lossy().into_owned())
+           .collect()
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).map_or(false, |m| {
+        m.is_file() && m.permissions().mode() & 0o111 != 0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_path() {
+        assert!(search("sh", OsStr::new("/does/not/exist:/bin")).is_some());
+        assert!(search("sh", OsStr::new("/does/not/exist")).is_none());
+        assert!(search("src", OsStr::new(":")).is_none());
+    }
+
+    #[test]
+    fn slash_names() {
+        assert_eq!(Some(PathBuf::from("/bin/sh")), find("/bin/sh"));
+        assert_eq!(None, find("/does/not/exist"));
+        assert_eq!(None, hashed("/bin/sh"));
+    }
+}
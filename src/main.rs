@@ -7,24 +7,31 @@ extern crate dirs;
 use std::{
     env,
     process,
+    ffi::CString,
     fs::File,
-    io::{self, Read},
+    io::{self, Read, BufRead, BufReader},
+    os::unix::ffi::OsStrExt,
     cell::RefCell,
     rc::Rc,
 };
 use docopt::{Docopt, ArgvMap, Value};
 use termion::is_tty;
 use dirs::home_dir;
-use nix::sys::wait::WaitStatus;
+use nix::{
+    unistd::{self, Pid},
+    sys::wait::WaitStatus,
+};
 use oursh::{
     repl::{
         self,
         Prompt,
     },
     program::{
-        parse_primary, parse_alternate,
-        Result, Error,
-        Run,
+        Program, Stream,
+        PrimaryProgram, AlternateProgram, ModernProgram,
+        Result, exit, exit_status,
+        runtime::{State, Options, Runtime},
+        posix::{lex, builtin::{Builtin, Dot}},
     },
     job::{Jobs, IO},
 };
@@ -32,28 +39,81 @@ use oursh::{
 // Write the Docopt usage string.
 const USAGE: &'static str = "
 Usage:
-    oursh    [options] [<file> [<arguments>...]]
-    oursh -c [options] <command_string> [<command_name> [<arguments>...]]
-    oursh -s [options] [<arguments>...]
+    oursh    [options] [-o <option>]... [<file> [<arguments>...]]
+    oursh -c [options] [-o <option>]... <command_string> [<command_name> [<arguments>...]]
+    oursh -s [options] [-o <option>]... [<arguments>...]
 
 Options:
     -h --help       Show this screen.
-    -v --verbose    Print extra information.
-    -a --ast        Print program ASTs.
+    -a              Export all assigned variables (allexport).
+    -C              Don't overwrite files with `>` (noclobber).
+    -e              Exit when a command fails (errexit).
+    -f              Disable pathname expansion (noglob).
+    -n              Read commands without running them (noexec).
+    -u              Fail to expand unset parameters (nounset).
+    -v              Print input as it's read (verbose).
+    -x              Print commands before running them (xtrace).
+    -o <option>     Set an option by name, see `set -o`.
+    --ast           Print program ASTs.
     -# --alternate  Use alternate program syntax.
+    --modern        Use modern program syntax.
+    --posix         Only accept POSIX syntax, without language blocks.
     --noprofile     Don't load and profile code on launch.
 ";
 
 // Our shell, for the greater good. Ready and waiting.
-fn main() -> Result<()> {
+fn main() {
     // Parse argv and exit the program with an error message if it fails.
     let args = Docopt::new(USAGE)
-                      .and_then(|d| d.argv(env::args().into_iter()).parse())
+                      .and_then(|d| d.options_first(true)
+                                     .argv(env::args().into_iter())
+                                     .parse())
                       .unwrap_or_else(|e| e.exit());
 
     // Elementary job management.
     let jobs: Jobs = Rc::new(RefCell::new(vec![]));
 
+    // The shell's own state, starting with the options given as flags.
+    let mut state = State::new("oursh");
+    for (name, flag) in Options::FLAGS {
+        if let Some(flag) = flag {
+            if args.get_bool(&format!("-{}", flag)) {
+                *state.options.get_mut(name).expect("flag without option") = true;
+            }
+        }
+    }
+    for name in args.get_vec("-o") {
+        match state.options.get_mut(name) {
+            Some(option) => *option = true,
+            None => {
+                eprintln!("oursh: {}: invalid option name", name);
+                process::exit(2);
+            },
+        }
+    }
+    // With `--posix` language blocks are syntax errors.
+    lex::strict(args.get_bool("--posix"));
+
+    // `$0` is the script or command name, and the rest of the arguments
+    // are the positional parameters.
+    if let Some(Value::Plain(Some(ref name))) = args.find("<command_name>") {
+        state.name = name.clone();
+    } else if let Some(Value::Plain(Some(ref file))) = args.find("<file>") {
+        state.name = file.clone();
+    }
+    state.args = args.get_vec("<arguments>").iter()
+                     .map(|a| a.to_string())
+                     .collect();
+    state.interactive = is_tty(&io::stdin()) &&
+                        !args.get_bool("-c") &&
+                        args.get_str("<file>").is_empty();
+    // `$PWD` always names the current directory, starting with the one the
+    // shell was launched in.
+    if let Ok(pwd) = state.pwd() {
+        env::set_var("PWD", pwd);
+    }
+    let runtime = state.shared();
+
     // Default inputs and outputs.
     let io = IO::default();
 
@@ -63,30 +123,34 @@ fn main() -> Result<()> {
     // - oursh_logout
     // - Others?
     if !args.get_bool("--noprofile") {
-        if let Some(mut path) = home_dir() {
-            path.push(".oursh_profile");
-            if let Ok(mut file) = File::open(path) {
-                let mut contents = String::new();
-                if let Ok(_) = file.read_to_string(&mut contents) {
-                    parse_and_run(io, jobs.clone(), &args)(&contents)?;
-                }
+        let profile = home_dir().map(|home| home.join(".oursh_profile"));
+        if let Some(path) = profile.filter(|path| path.is_file()) {
+            // The profile is sourced just like `. ~/.oursh_profile`, its
+            // errors are reported, but don't stop the shell.
+            let argv = vec![
+                CString::new(".").expect("error in name"),
+                CString::new(path.as_os_str().as_bytes()).expect("error in path"),
+            ];
+            if let Err(e) = Dot::run(argv, io, jobs.clone(), runtime.clone()) {
+                eprintln!("oursh: {}", e);
+                runtime.borrow_mut().status = e.status();
             }
         }
     }
 
-    if let Some(Value::Plain(Some(ref c))) = args.find("<command_string>") {
-        parse_and_run(io, jobs, &args)(c)
+    let result = if let Some(Value::Plain(Some(ref c))) = args.find("<command_string>") {
+        run(c.as_bytes(), io, &jobs, &runtime, &args)
     } else if let Some(Value::Plain(Some(ref filename))) = args.find("<file>") {
-        let mut file = File::open(filename)
-            .expect(&format!("error opening file: {}", filename));
-
-        // Fill a string buffer from the file.
-        let mut text = String::new();
-        file.read_to_string(&mut text)
-            .expect("error reading file");
+        let file = match File::open(filename) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("oursh: {}: {}", filename, e);
+                process::exit(127);
+            },
+        };
 
-        // Run the program.
-        parse_and_run(io, jobs, &args)(&text)
+        // Run the program, as it's read.
+        run(BufReader::new(file), io, &jobs, &runtime, &args)
     } else {
         // Standard input file descriptor (0), used for user input from the
         // user of the shell.
@@ -98,7 +162,7 @@ fn main() -> Result<()> {
         // args.get_bool("-s")
 
         // Process text in raw mode style if we're attached to a tty.
-        if is_tty(&stdin) {
+        if runtime.borrow().interactive {
             // Standard output file descriptor (1), used to display program output
             // to the user of the shell.
             let stdout = io::stdout();
@@ -111,34 +175,59 @@ fn main() -> Result<()> {
             // Start a program running repl.
             // A styled static (for now) prompt.
             let prompt = Prompt::sh_style();
-            repl::start(prompt, stdin, stdout, parse_and_run(io, jobs, &args));
-            Ok(())
+            let runner = parse_and_run(io, jobs, runtime, &args);
+            // An interactive shell never exits on an error.
+            repl::start(prompt, stdin, stdout, |text: &String| {
+                runner(text).map(|_| ())
+            });
+            exit(0);
         } else {
-            // Fill a string buffer from STDIN.
-            let mut text = String::new();
-            stdin.lock().read_to_string(&mut text).unwrap();
-
-            // Run the program.
-            match parse_and_run(io, jobs, &args)(&text) {
-                Ok(u) => Ok(u),
-                Err(Error::Read) => {
-                    process::exit(1);
-                },
-                Err(Error::Parse) => {
-                    process::exit(2);
-                },
-                Err(Error::Runtime) => {
-                    // TODO: Exit with the last status code?
-                    process::exit(127);
-                }
-            }
+            // Run the program, as it's read. Nothing past the command being
+            // run is read, so the commands can read the rest themselves.
+            run(BufReader::with_capacity(1, Stdin), io, &jobs, &runtime, &args)
         }
+    };
+
+    // Exit with the status of the last command, or of the error which
+    // stopped the shell.
+    match result {
+        Ok(status) => exit(exit_status(&status)),
+        Err(e) => exit(e.status()),
     }
 }
 
-fn parse_and_run<'a>(io: IO, jobs: Jobs, args: &'a ArgvMap)
--> impl Fn(&String) -> Result<()> + 'a {
-    move |text: &String| {
+// Parse and run the given text, reporting any errors to STDERR.
+fn parse_and_run<'a>(io: IO, jobs: Jobs, runtime: Runtime, args: &'a ArgvMap)
+-> impl Fn(&String) -> Result<WaitStatus> + 'a {
+    move |text: &String| run(text.as_bytes(), io, &jobs, &runtime, args)
+}
+
+// Parse and run each command read from `reader` as soon as it's complete,
+// reporting any errors to STDERR.
+fn run<R: BufRead>(reader: R, io: IO, jobs: &Jobs, runtime: &Runtime, args: &ArgvMap)
+    -> Result<WaitStatus>
+{
+    let result = if args.get_bool("-#") {
+        run_stream(AlternateProgram::stream(reader), io, jobs, runtime, args)
+    } else if args.get_bool("--modern") {
+        run_stream(ModernProgram::stream(reader), io, jobs, runtime, args)
+    } else {
+        run_stream(PrimaryProgram::stream(reader), io, jobs, runtime, args)
+    };
+
+    if let Err(ref e) = result {
+        eprintln!("oursh: {}", e);
+    }
+    result
+}
+
+fn run_stream<P, R>(mut programs: Stream<P, R>, io: IO, jobs: &Jobs,
+                    runtime: &Runtime, args: &ArgvMap)
+    -> Result<WaitStatus>
+    where P: Program, R: BufRead
+{
+    let mut last = WaitStatus::Exited(Pid::this(), 0);
+    while let Some(program) = programs.next() {
         jobs.borrow_mut().retain(|job| {
             match job.1.status() {
                 Ok(WaitStatus::StillAlive) => {
@@ -163,44 +252,42 @@ fn parse_and_run<'a>(io: IO, jobs: Jobs, args: &'a ArgvMap)
             }
         });
 
-        if text.is_empty() {
-            return Ok(());
+        // Echo the input as it's read with the `verbose` option.
+        if runtime.borrow().options.verbose {
+            let text = programs.text();
+            eprint!("{}", text);
+            if !text.ends_with('\n') {
+                eprintln!();
+            }
         }
 
-        // Parse with the primary grammar and run each command in order.
-        if args.get_bool("-#") {
-            let program = match parse_alternate(text.as_bytes()) {
-                Ok(program) => program,
-                Err(e) => {
-                    eprintln!("{:?}: {:#?}", e, text);
-                    return Err(e);
-                }
-            };
+        // Blank lines and comments don't change the status.
+        let program = program?;
+        if program.commands().is_empty() {
+            continue;
+        }
 
-            // Print the program if the flag is given.
-            if args.get_bool("--ast") {
-                eprintln!("{:#?}", program);
-            }
+        // Print the program if the flag is given.
+        if args.get_bool("--ast") {
+            eprintln!("{:#?}", program);
+        }
 
-            // Run it!
-            program.run(false, io, jobs.clone()).map(|_| ())
-        } else {
-            let program = match parse_primary(text.as_bytes()) {
-                Ok(program) => program,
-                Err(e) => {
-                    eprintln!("{:?}: {:#?}", e, text);
-                    return Err(e);
-                }
-            };
+        // Run it!
+        last = program.run(false, io, jobs.clone(), runtime.clone())?;
+    }
+    Ok(last)
+}
 
-            // Print the program if the flag is given.
-            if args.get_bool("--ast") {
-                eprintln!("{:#?}", program);
-            }
+// Standard input, read without buffering ahead, so commands started by the
+// shell can read the rest of it.
+struct Stdin;
 
-            // Run it!
-            program.run(false, io, jobs.clone()).map(|_| ())
-        }
+impl Read for Stdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        unistd::read(0, buf).map_err(|e| match e.as_errno() {
+            Some(errno) => io::Error::from_raw_os_error(errno as i32),
+            None => io::Error::new(io::ErrorKind::Other, e),
+        })
     }
 }
 
@@ -20,7 +20,6 @@
 //! the [`modern`](program::modern) module docs.
 //!
 //! ```sh
-//! # WIP
 //! for i in (0..10) { echo $i }
 //! ```
 //!
@@ -55,14 +54,37 @@
 //!
 //! Strict POSIX compatibility can be enabled by removing this feature alone.
 //!
-//! - TODO #5: Parse sequence of programs from stream.
+//! ### Streams
+//!
+//! Scripts are run as they're read, one complete command at a time, with
+//! [`Program::stream`](Program::stream). A command only runs after all
+//! those before it have, and a syntax error only stops the commands after
+//! it.
+//!
+//! ```
+//! use oursh::program::{Program, PosixProgram};
+//!
+//! let mut programs = PosixProgram::stream(b"echo 1\necho '2\n3'\n)" as &[u8]);
+//! assert!(programs.next().unwrap().is_ok());
+//! assert_eq!("echo 1\n", programs.text());
+//! assert!(programs.next().unwrap().is_ok());
+//! assert_eq!("echo '2\n3'\n", programs.text());
+//! assert!(programs.next().unwrap().is_err());
+//! assert!(programs.next().is_none());
+//! ```
+//!
 //! - TODO #5: Partial parses for readline-ish / syntax highlighting.
 
 use std::{
     result,
+    error,
+    fmt,
+    io,
     ffi::CString,
     fmt::Debug,
     io::BufRead,
+    marker::PhantomData,
+    process,
 };
 use nix::{
     unistd::Pid,
@@ -71,27 +93,148 @@ use nix::{
 use crate::{
     job::{IO, Jobs},
 };
+use self::runtime::Runtime;
 
 /// Convenience type for results with program errors.
 pub type Result<T> = result::Result<T, Error>;
 
 /// A comprehensive error type for the operation of programs.
+///
+/// These are errors of the *shell*, not of the commands it runs. A command
+/// which exits with a non-zero status ran just fine, and its status is
+/// returned as an `Ok(WaitStatus)` like any other.
+///
+/// Following the consequences of shell errors in POSIX (3§2.8.1), every
+/// error here causes a non-interactive shell to exit, with the status given
+/// by [`Error::status`](Error::status). Failures which only affect a single
+/// command, like a redirection for a regular utility, are reported by the
+/// command and result in a non-zero status instead.
 #[derive(Debug)]
 pub enum Error {
     /// A general issue reading the program.
-    // TODO: Wrap an io error?
-    Read,
-    /// An error within the lexer or parser.
-    // TODO: Wrap both our lex::Error and ParseError.
-    Parse,
-    /// An error encountered during the evaluation of a program.
-    // TODO: Propagate status.
-    // TODO: Just wrap an Wait/ExitStatus?
-    Runtime,
+    Read(io::Error),
+    /// An error within the lexer or parser, with the span of the program
+    /// text where it was found.
+    ///
+    /// The error is `incomplete` when the text simply ended too soon, like
+    /// in an open quote or after a `|`, so more text may still parse.
+    Parse {
+        span: (usize, usize),
+        message: String,
+        incomplete: bool,
+    },
+    /// A system error running a command, like a failed `fork`.
+    Runtime(nix::Error),
+    /// The script of a language block couldn't be written for its
+    /// interpreter.
+    Block(io::Error),
+    /// A redirection for a special builtin (or compound command) failed.
+    Redirect {
+        filename: String,
+        error: io::Error,
+    },
+    /// A special builtin was given invalid arguments.
+    Builtin {
+        name: String,
+        message: String,
+    },
+    /// A word couldn't be expanded, like an unset parameter with the
+    /// `nounset` option.
+    Expansion {
+        parameter: String,
+        message: String,
+    },
+}
+
+impl Error {
+    /// The exit status of a shell which stops because of this error.
+    pub fn status(&self) -> i32 {
+        match self {
+            Error::Read(_) => 1,
+            Error::Parse { .. } => 2,
+            Error::Runtime(_) => 1,
+            Error::Block(_) => 1,
+            Error::Redirect { .. } => 1,
+            Error::Builtin { .. } => 2,
+            Error::Expansion { .. } => 1,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Read(e) => write!(f, "error reading program: {}", e),
+            Error::Parse { span: (s, e), message, .. } => {
+                write!(f, "syntax error at {}-{}: {}", s, e, message)
+            },
+            Error::Runtime(e) => write!(f, "{}", e),
+            Error::Block(e) => write!(f, "error writing block: {}", e),
+            Error::Redirect { filename, error } => {
+                write!(f, "{}: {}", filename, error)
+            },
+            Error::Builtin { name, message } => {
+                write!(f, "{}: {}", name, message)
+            },
+            Error::Expansion { parameter, message } => {
+                write!(f, "{}: {}", parameter, message)
+            },
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Read(e) => Some(e),
+            Error::Runtime(e) => Some(e),
+            Error::Block(e) => Some(e),
+            Error::Redirect { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<nix::Error> for Error {
+    fn from(error: nix::Error) -> Self {
+        Error::Runtime(error)
+    }
+}
+
+/// The numeric exit status (as in `$?`) of a finished command.
+///
+/// Commands killed by a signal report `128` plus the signal number.
+///
+/// ```
+/// use nix::{unistd::Pid, sys::{wait::WaitStatus, signal::Signal}};
+/// use oursh::program::exit_status;
+///
+/// assert_eq!(3, exit_status(&WaitStatus::Exited(Pid::this(), 3)));
+/// let killed = WaitStatus::Signaled(Pid::this(), Signal::SIGKILL, false);
+/// assert_eq!(137, exit_status(&killed));
+/// ```
+pub fn exit_status(status: &WaitStatus) -> i32 {
+    match *status {
+        WaitStatus::Exited(_, code) => code,
+        WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+        WaitStatus::Stopped(_, signal) => 128 + signal as i32,
+        _ => 0,
+    }
+}
+
+/// Exit the shell with the given status.
+///
+/// Unlike `process::exit` the interpreters of any `{#lang@name ...}`
+/// sessions are ended first, rather than left behind.
+pub fn exit(code: i32) -> ! {
+    #[cfg(feature = "shebang-block")]
+    posix::session::end();
+    process::exit(code)
 }
 
 pub trait Run {
-    fn run(&self, background: bool, io: IO, jobs: Jobs) -> Result<WaitStatus>;
+    fn run(&self, background: bool, io: IO, jobs: Jobs, runtime: Runtime)
+        -> Result<WaitStatus>;
 }
 
 /// A program is as large as a file or as small as a line.
@@ -112,20 +255,98 @@ pub trait Program: Sized + Debug + Run {
     /// Parse a whole program from the given `reader`.
     fn parse<R: BufRead>(reader: R) -> Result<Self>;
 
+    /// Parse the complete commands of a program from the given `reader`,
+    /// each as soon as it has been read.
+    fn stream<R: BufRead>(reader: R) -> Stream<Self, R> {
+        Stream {
+            reader,
+            text: String::new(),
+            offset: 0,
+            program: PhantomData,
+        }
+    }
+
     /// Return a list of all the commands in this program.
     fn commands(&self) -> &[Box<Self::Command>];
 }
 
 impl<P: Program> Run for P {
-    fn run(&self, background: bool, io: IO, jobs: Jobs) -> Result<WaitStatus> {
+    fn run(&self, background: bool, io: IO, jobs: Jobs, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
         let mut last = WaitStatus::Exited(Pid::this(), 0);
         for command in self.commands().iter() {
-            last = command.run(background, io, jobs.clone())?;
+            // With `noexec` the remaining commands are only read.
+            {
+                let state = runtime.borrow();
+                if state.options.noexec && !state.interactive {
+                    break;
+                }
+            }
+            last = command.run(background, io, jobs.clone(), runtime.clone())?;
         }
         Ok(last)
     }
 }
 
+/// An incremental parser over the lines of a reader, yielding a program for
+/// each complete command (or line of commands) as soon as it's read.
+///
+/// Text which is incomplete, like an open quote or block, a trailing `\`,
+/// `|`, `&&` or `||`, or an `if` without its `fi`, is read along with the
+/// next line before trying again. Any other syntax error is yielded right
+/// away in place of the program, with its span counted from the start of the
+/// whole stream.
+pub struct Stream<P, R> {
+    reader: R,
+    text: String,
+    offset: usize,
+    program: PhantomData<P>,
+}
+
+impl<P, R> Stream<P, R> {
+    /// The text of the last program (or error) yielded.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl<P: Program, R: BufRead> Iterator for Stream<P, R> {
+    type Item = Result<P>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.offset += self.text.len();
+        self.text.clear();
+        loop {
+            let read = match self.reader.read_line(&mut self.text) {
+                Ok(read) => read,
+                Err(e) => return Some(Err(Error::Read(e))),
+            };
+            if self.text.is_empty() {
+                return None;
+            }
+
+            // Until EOF, an odd number of `\` before the newline continues
+            // the line.
+            let escapes = self.text.trim_end_matches('\n').len() -
+                          self.text.trim_end_matches('\n')
+                                   .trim_end_matches('\\').len();
+            if read > 0 && escapes % 2 == 1 {
+                continue;
+            }
+
+            match P::parse(self.text.as_bytes()) {
+                Err(Error::Parse { incomplete: true, .. }) if read > 0 => continue,
+                Err(Error::Parse { span: (s, e), message, incomplete }) => {
+                    let span = (s + self.offset, e + self.offset);
+                    return Some(Err(Error::Parse { span, message, incomplete }));
+                },
+                result => return Some(result),
+            }
+        }
+    }
+}
+
 /// A command is a task given by the user as part of a [`Program`](Program).
 ///
 /// Each command is handled by a `Job`, and a single command may be run
@@ -152,9 +373,8 @@ pub trait Command: Sized + Debug + Run {
 // TODO: This should be `ModernProgram`.
 pub type PrimaryProgram = PosixProgram;
 
-/// TODO: alt explain
-// TODO: This should be `PosixProgram`.
-pub type AlternateProgram = BasicProgram;
+/// The alternate program type, used with `--alternate` and `{# ...}` blocks.
+pub type AlternateProgram = PosixProgram;
 
 /// Parse a program of the primary type.
 ///
@@ -202,7 +422,11 @@ pub fn parse<P: Program, R: BufRead>(reader: R) -> Result<P> {
 // If reading this code were like sking, you'd now be hitting blues. ASTs and
 // language semantics are somewhat tricky subjects.
 
+pub mod runtime;
+
 pub mod basic;
 pub use self::basic::Program as BasicProgram;
 pub mod posix;
 pub use self::posix::Program as PosixProgram;
+pub mod modern;
+pub use self::modern::Program as ModernProgram;
@@ -6,7 +6,7 @@ use std::{
 use nix::sys::wait::WaitStatus;
 use crate::{
     job::{Job, Jobs},
-    program::{Result, Error, IO},
+    program::{Result, Error, IO, runtime::Runtime},
 };
 
 
@@ -26,9 +26,7 @@ impl super::Program for Program {
     /// ```
     fn parse<R: BufRead>(mut reader: R) -> Result<Self> {
         let mut command = String::new();
-        if reader.read_to_string(&mut command).is_err() {
-            return Err(Error::Read);
-        }
+        reader.read_to_string(&mut command).map_err(Error::Read)?;
         Ok(Program(vec![box Command(command)]))
     }
 
@@ -46,23 +44,19 @@ pub struct Command(String);
 impl super::Command for Command {}
 
 impl super::Run for Command {
-    fn run(&self, background: bool, io: IO, jobs: Jobs) -> Result<WaitStatus> {
+    fn run(&self, background: bool, io: IO, jobs: Jobs, _: Runtime)
+        -> Result<WaitStatus>
+    {
         let mut job = Job::new(self.0.split_whitespace().map(|a| {
             CString::new(a).expect("error reading argument")
         }).collect());
 
-        let status = if background {
-            let status = job.fork(io);
+        if background {
+            let status = job.fork(io)?;
             jobs.borrow_mut().push(("???".into(), job));
-            status
+            Ok(status)
         } else {
-            job.fork_and_wait(io)
-        };
-        match status {
-            Ok(WaitStatus::Exited(p, c)) if c == 0 => {
-                Ok(WaitStatus::Exited(p, c))
-            },
-            _ => Err(Error::Runtime),
+            Ok(job.fork_and_wait(io)?)
         }
     }
 }
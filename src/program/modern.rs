@@ -0,0 +1,321 @@
+//! The modern shell language, a saner syntax for new scripts.
+//!
+//! Statements are separated by newlines or `;`, and every compound statement
+//! uses braces, with fish-like blocks, ranges and functions.
+//!
+//! ```sh
+//! let name = world
+//!
+//! fn greet(who, greeting) {
+//!     echo "$greeting, $who!"
+//! }
+//!
+//! for i in (1..=3) {
+//!     if test $i = 2 { greet $name hello } else { echo $i }
+//! }
+//!
+//! while ! test -f /tmp/ready { sleep 1 }
+//! ls | wc -l > count.txt && cat count.txt
+//! ```
+//!
+//! Words are written and expanded like POSIX words, with quotes, parameters
+//! and command substitution, but an expansion is never split into more
+//! fields, so `$file` is always a single argument. Only the items of a `for`
+//! are split, so `for f in $(ls) { ... }` works as expected.
+//!
+//! ### Bridges to POSIX
+//!
+//! Both languages share the shell's variables, jobs and builtins, and each
+//! can run the other in a language block, in the same process.
+//!
+//! ```sh
+//! {#posix for i in 1 2 3; do echo $i; done}
+//! ```
+//!
+//! And from a POSIX program, `{#modern for i in (1..4) { echo $i }}`. Any
+//! other language block works the same way it does in POSIX.
+use std::io::BufRead;
+use lalrpop_util::ParseError;
+use nix::{
+    sys::wait::WaitStatus,
+    unistd::Pid,
+};
+use crate::{
+    job::Jobs,
+    program::{
+        Result, Error, IO, Run, exit, exit_status,
+        posix::{self, expand, ast::is_name},
+        runtime::{Function, Runtime},
+    },
+};
+use self::ast::Items;
+
+// Re-exports.
+pub use self::ast::Program;
+pub use self::ast::Command;
+
+/// The syntax and semantics of a single modern command.
+///
+/// ```
+/// use oursh::program::Program as ProgramTrait;
+/// use oursh::program::modern::ast::Program;
+///
+/// assert!(Program::parse(b"for i in (0..10) { echo $i }" as &[u8]).is_ok());
+/// assert!(Program::parse(b"if true {" as &[u8]).is_err());
+/// ```
+impl super::Program for Program {
+    type Command = Command;
+
+    fn parse<R: BufRead>(mut reader: R) -> Result<Self> {
+        let mut string = String::new();
+        reader.read_to_string(&mut string).map_err(Error::Read)?;
+
+        let lexer = lex::Lexer::new(&string);
+        let parser = parse::ProgramParser::new();
+        parser.parse(&string, lexer).map_err(|e| {
+            let incomplete = match e {
+                ParseError::UnrecognizedEOF { .. } => true,
+                ParseError::User { error: lex::Error::Unterminated(..) } => true,
+                _ => false,
+            };
+            let (span, message) = match e {
+                ParseError::InvalidToken { location } => {
+                    ((location, location), "invalid token".into())
+                },
+                ParseError::UnrecognizedToken { token, expected } => {
+                    let (s, t, e) = token;
+                    ((s, e), format!("unexpected token {:?}, expecting one of: {}",
+                                     t, expected.join(", ")))
+                },
+                ParseError::UnrecognizedEOF { location, expected }=> {
+                    ((location, string.len()),
+                     format!("unexpected EOF, expecting one of: {}",
+                             expected.join(", ")))
+                }
+                ParseError::ExtraToken { token: (s, t, e) } => {
+                    ((s, e), format!("extra token {:?}", t))
+                }
+                ParseError::User { error } => match error {
+                    lex::Error::Unterminated(s, e) => {
+                        ((s, e), "unterminated quote, expansion or block".into())
+                    },
+                    lex::Error::Block(s, e) => {
+                        ((s, e), "language blocks aren't POSIX".into())
+                    },
+                },
+            };
+            Error::Parse { span, message, incomplete }
+        })
+    }
+
+    fn commands(&self) -> &[Box<Self::Command>] {
+        &self.0[..]
+    }
+}
+
+// The semantics of a single modern command.
+impl super::Command for Command {}
+
+impl super::Run for Command {
+    fn run(&self, background: bool, io: IO, jobs: Jobs, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let depth = runtime.borrow().traced.len();
+        let status = self.execute(background, io, jobs, runtime.clone());
+        let traced = runtime.borrow_mut().traced.split_off(depth).pop();
+        let status = status?;
+
+        let mut state = runtime.borrow_mut();
+        state.status = exit_status(&status);
+
+        if let (Some((line, start)), false) = (traced, background) {
+            eprintln!("{} ({:.3}s, status {})",
+                      line, start.elapsed().as_secs_f64(), state.status);
+        }
+
+        // With `errexit` failing commands exit the shell, just like POSIX.
+        match self {
+            Command::Simple(..) | Command::Let(..) | Command::Pipeline(..) => {
+                if state.options.errexit &&
+                   state.conditional == 0 &&
+                   state.status != 0
+                {
+                    exit(state.status);
+                }
+            },
+            _ => {},
+        }
+
+        Ok(status)
+    }
+}
+
+impl Command {
+    fn execute(&self, background: bool, io: IO, jobs: Jobs, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        match *self {
+            // Words are never split into more fields, otherwise these run
+            // just like POSIX simple commands.
+            Command::Simple(ref words, ref redirects) => {
+                let mut fields = vec![];
+                for word in words {
+                    fields.push(expand::string(&word.0, &runtime)?);
+                }
+                posix::simple(vec![], fields, redirects, background, io, jobs, runtime)
+            },
+            Command::Let(ref name, ref value) => {
+                if !is_name(name) {
+                    eprintln!("oursh: let: {}: invalid variable name", name);
+                    return Ok(WaitStatus::Exited(Pid::this(), 2));
+                }
+                runtime.borrow_mut().substitution = None;
+                let value = expand::string(&value.0, &runtime)?;
+                let mut state = runtime.borrow_mut();
+                state.set(name, &value);
+                let code = state.substitution.unwrap_or(0);
+                Ok(WaitStatus::Exited(Pid::this(), code))
+            },
+            Command::Not(ref command) => {
+                match posix::conditional(&runtime, || {
+                    command.run(false, io, jobs.clone(), runtime.clone())
+                })? {
+                    WaitStatus::Exited(p, c) => {
+                        Ok(WaitStatus::Exited(p, (c == 0) as i32))
+                    }
+                    s => Ok(s),
+                }
+            },
+            Command::And(ref left, ref right) |
+            Command::Or(ref left, ref right) => {
+                let and = matches!(self, Command::And(..));
+                let status = posix::conditional(&runtime, || {
+                    left.run(false, io, jobs.clone(), runtime.clone())
+                })?;
+                if (exit_status(&status) == 0) == and {
+                    right.run(false, io, jobs.clone(), runtime.clone())
+                } else {
+                    Ok(status)
+                }
+            },
+            Command::Pipeline(ref left, ref right) => {
+                posix::pipeline(&**left, &**right, io, &jobs, &runtime)
+            },
+            Command::Background(ref command) => {
+                command.run(true, io, jobs.clone(), runtime.clone())?;
+                Ok(WaitStatus::Exited(Pid::this(), 0))
+            },
+            Command::Block(ref commands) => {
+                let mut last = WaitStatus::Exited(Pid::this(), 0);
+                for command in commands.iter() {
+                    last = command.run(false, io, jobs.clone(), runtime.clone())?;
+                }
+                Ok(last)
+            },
+            Command::If(ref cond, ref then, ref els) => {
+                match posix::conditional(&runtime, || {
+                    cond.run(false, io, jobs.clone(), runtime.clone())
+                })? {
+                    WaitStatus::Exited(_, c) if c == 0 => {
+                        then.run(false, io, jobs.clone(), runtime.clone())
+                    },
+                    _ => match els {
+                        Some(els) => els.run(false, io, jobs.clone(), runtime.clone()),
+                        None => Ok(WaitStatus::Exited(Pid::this(), 0)),
+                    },
+                }
+            },
+            Command::While(ref cond, ref body) => {
+                let mut last = WaitStatus::Exited(Pid::this(), 0);
+                loop {
+                    let status = posix::conditional(&runtime, || {
+                        cond.run(false, io, jobs.clone(), runtime.clone())
+                    })?;
+                    if exit_status(&status) != 0 {
+                        break;
+                    }
+                    last = body.run(false, io, jobs.clone(), runtime.clone())?;
+                }
+                Ok(last)
+            },
+            Command::For(ref name, ref items, ref body) => {
+                let items: Box<dyn Iterator<Item = String>> = match items {
+                    Items::Words(words) => {
+                        let mut items = vec![];
+                        for word in words {
+                            items.append(&mut expand::fields(&word.0, &runtime)?);
+                        }
+                        box items.into_iter()
+                    },
+                    Items::Range(word) => {
+                        let text = expand::string(&word.0, &runtime)?;
+                        match range(&text) {
+                            Some((start, end)) => box (start..end).map(|i| i.to_string()),
+                            None => {
+                                eprintln!("oursh: for: ({}): invalid range", text);
+                                return Ok(WaitStatus::Exited(Pid::this(), 1));
+                            },
+                        }
+                    },
+                };
+
+                let mut last = WaitStatus::Exited(Pid::this(), 0);
+                for item in items {
+                    runtime.borrow_mut().set(name, &item);
+                    last = body.run(false, io, jobs.clone(), runtime.clone())?;
+                }
+                Ok(last)
+            },
+            Command::Function(ref name, ref params, ref body) => {
+                let function = Function {
+                    params: params.clone(),
+                    body: body.clone(),
+                };
+                runtime.borrow_mut().functions.insert(name.clone(), function);
+                Ok(WaitStatus::Exited(Pid::this(), 0))
+            },
+            Command::Lang(ref interpreter, ref text) => {
+                let command = posix::Command::Lang(interpreter.clone(), text.clone());
+                command.run(background, io, jobs, runtime)
+            },
+        }
+    }
+}
+
+// The bounds of a range `start..end`, or `start..=end` including the end.
+fn range(text: &str) -> Option<(i64, i64)> {
+    let (start, end, inclusive) = match text.find("..=") {
+        Some(i) => (&text[..i], &text[i + 3..], true),
+        None => {
+            let i = text.find("..")?;
+            (&text[..i], &text[i + 2..], false)
+        },
+    };
+    let start = start.trim().parse().ok()?;
+    let end: i64 = end.trim().parse().ok()?;
+    Some((start, if inclusive { end.checked_add(1)? } else { end }))
+}
+
+// The modern AST data structures.
+pub mod ast;
+
+// The lexer of the modern language.
+pub mod lex;
+
+lalrpop_mod!(
+    /// LALRPOP generated parser module.
+    pub parse, "/program/modern.rs");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(Some((0, 10)), range("0..10"));
+        assert_eq!(Some((1, 4)), range("1..=3"));
+        assert_eq!(Some((-2, 2)), range("-2..2"));
+        assert_eq!(None, range("0..x"));
+        assert_eq!(None, range("10"));
+    }
+}
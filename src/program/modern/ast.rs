@@ -0,0 +1,131 @@
+//! Abstract Syntax Tree for the modern language.
+use std::rc::Rc;
+use crate::program::posix::ast::{Interpreter, Redirect};
+
+/// A program is the result of parsing a sequence of statements.
+#[derive(Debug, Clone)]
+pub struct Program(pub Vec<Box<Command>>);
+
+/// A single statement of the modern language.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// A command with its arguments, and redirections after them.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// date --iso-8601 > now.txt
+    /// ```
+    Simple(Vec<Word>, Vec<Redirect>),
+
+    /// Assign a (single, never split) value to a variable.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// let greeting = "hello $USER"
+    /// ```
+    Let(String, Word),
+
+    /// Performs boolean negation to the status code of the inner command.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// ! grep -q password data.txt
+    /// ```
+    Not(Box<Command>),
+
+    /// Run the second command only when the first succeeds.
+    And(Box<Command>, Box<Command>),
+
+    /// Run the second command only when the first fails.
+    Or(Box<Command>, Box<Command>),
+
+    /// Run a command's output through to the input of another.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// ls | wc -l
+    /// ```
+    Pipeline(Box<Command>, Box<Command>),
+
+    /// Run a command without waiting for it to finish.
+    Background(Box<Command>),
+
+    /// A sequence of statements in braces.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// { date; uptime }
+    /// ```
+    Block(Vec<Box<Command>>),
+
+    /// Run the block if the condition succeeds, otherwise the optional
+    /// `else` block. `else if` is simply a nested `If`.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// if test -f Cargo.toml { cargo build } else { make }
+    /// ```
+    If(Box<Command>, Box<Command>, Option<Box<Command>>),
+
+    /// Run the block for as long as the condition succeeds.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// while test ! -f /tmp/ready { sleep 1 }
+    /// ```
+    While(Box<Command>, Box<Command>),
+
+    /// Run the block once for each item, with the variable set to it.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// for i in (0..10) { echo $i }
+    /// for f in Cargo.toml README.md { wc -l $f }
+    /// ```
+    For(String, Items, Box<Command>),
+
+    /// Define a function, which is called like any other command, with its
+    /// parameters set to the arguments.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// fn greet(name, greeting) { echo "$greeting, $name" }
+    /// greet world hello
+    /// ```
+    Function(String, Vec<String>, Rc<Command>),
+
+    /// A language block, run exactly as a POSIX program would, including
+    /// `{#posix ...}` which shares this shell's state.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// {#posix for i in 1 2 3; do echo $i; done}
+    /// {#!/usr/bin/env ruby; puts :sym}
+    /// ```
+    Lang(Interpreter, String),
+}
+
+/// A parsed word, exactly as it was written, see the POSIX
+/// [`Word`](crate::program::posix::ast::Word).
+#[derive(Debug, Clone)]
+pub struct Word(pub String);
+
+/// The items of a `for` statement.
+#[derive(Debug, Clone)]
+pub enum Items {
+    /// Words, each expanded into any number of items.
+    Words(Vec<Word>),
+    /// A range of integers `(start..end)`, or with `..=` including the end,
+    /// whose bounds are expanded first.
+    Range(Word),
+}
@@ -0,0 +1,369 @@
+//! Lexer for the modern language.
+//!
+//! Words are kept exactly as written, like the POSIX lexer, and are only
+//! expanded right before they're used. Keywords are only recognized where a
+//! statement starts, so `echo if` is just two words, and `in` and `=` only
+//! after `for name` and `let name`.
+//!
+//! ```
+//! use oursh::program::modern::lex::{Lexer, Token};
+//!
+//! let tokens: Vec<_> = Lexer::new("for i in (0..3) { echo $i }")
+//!     .map(|t| t.unwrap().1)
+//!     .collect();
+//! assert_eq!(Token::For, tokens[0]);
+//! assert_eq!(Token::In, tokens[2]);
+//! assert_eq!(Token::Word("0..3"), tokens[4]);
+//! ```
+use std::{
+    collections::VecDeque,
+    iter::Peekable,
+    str::CharIndices,
+};
+use crate::program::posix;
+
+/// A result type wrapping a token with start and end locations.
+pub type Span<T, E> = Result<(usize, T, usize), E>;
+
+/// A lexer error.
+#[derive(Debug)]
+pub enum Error {
+    /// A quote, expansion or block without its end.
+    Unterminated(usize, usize),
+    /// A `{#...}` language block, in strict POSIX mode.
+    Block(usize, usize),
+}
+
+/// Every token in the language, these are the terminals of the grammar.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Token<'input> {
+    Linefeed,
+    Semi,
+    Amper,
+    Pipe,
+    And,
+    Or,
+    Bang,
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    Equals,
+    Great,
+    DGreat,
+    GreatAnd,
+    Less,
+    LessAnd,
+    If,
+    Else,
+    While,
+    For,
+    In,
+    Fn,
+    Let,
+    Word(&'input str),
+    IoNumber(usize),
+    HashLang(&'input str),
+    Shebang(&'input str),
+    Text(&'input str),
+}
+
+/// A lexer to feed the parser generated by LALRPOP.
+pub struct Lexer<'input> {
+    input: &'input str,
+    chars: Peekable<CharIndices<'input>>,
+    /// The text and closing brace of a block, after its header.
+    pending: VecDeque<Span<Token<'input>, Error>>,
+    /// The last two tokens, for the keywords which depend on them.
+    previous: (Option<Token<'input>>, Option<Token<'input>>),
+    /// True where a statement may start, and where separators are ignored,
+    /// so blank lines and trailing `;` are allowed.
+    separated: bool,
+}
+
+impl<'input> Lexer<'input> {
+    /// Create a new lexer from an input &str.
+    pub fn new(input: &'input str) -> Self {
+        Lexer {
+            input,
+            chars: input.char_indices().peekable(),
+            pending: VecDeque::new(),
+            previous: (None, None),
+            separated: true,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    // The position of the next character.
+    fn position(&mut self) -> usize {
+        self.chars.peek().map_or(self.input.len(), |&(i, _)| i)
+    }
+
+    // Consume the next character if it's `c`.
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    // A keyword is only a keyword where a statement starts, except for `in`
+    // and `=`, which follow `for name` and `let name`.
+    fn keyword(&self, word: &'input str) -> Token<'input> {
+        match self.previous {
+            (Some(Token::For), Some(Token::Word(_))) if word == "in" => Token::In,
+            (Some(Token::Let), Some(Token::Word(_))) if word == "=" => Token::Equals,
+            (_, Some(_)) => Token::Word(word),
+            _ => match word {
+                "if"    => Token::If,
+                "else"  => Token::Else,
+                "while" => Token::While,
+                "for"   => Token::For,
+                "fn"    => Token::Fn,
+                "let"   => Token::Let,
+                word    => Token::Word(word),
+            },
+        }
+    }
+
+    // Consume the rest of a word, started at `start`.
+    fn word(&mut self, start: usize) -> Span<Token<'input>, Error> {
+        while let Some(c) = self.peek() {
+            match c {
+                c if c.is_whitespace() => break,
+                ';' | '&' | '|' | '{' | '}' | '(' | ')' | '<' | '>' => break,
+                _ => {
+                    self.chars.next();
+                    self.word_part(start, c)?;
+                },
+            }
+        }
+        let end = self.position();
+        let word = &self.input[start..end];
+
+        if word.chars().all(|c| c.is_ascii_digit()) {
+            if let Some('<') | Some('>') = self.peek() {
+                if let Ok(n) = word.parse() {
+                    return Ok((start, Token::IoNumber(n), end));
+                }
+            }
+        }
+        Ok((start, self.keyword(word), end))
+    }
+
+    // Consume the rest of a quote, escape or expansion, after its first
+    // character `c`.
+    fn word_part(&mut self, start: usize, c: char) -> Result<(), Error> {
+        match c {
+            '\\' => { self.chars.next(); },
+            '\'' => {
+                if self.chars.find(|&(_, c)| c == '\'').is_none() {
+                    return Err(Error::Unterminated(start, self.input.len()));
+                }
+            },
+            '"' => loop {
+                match self.chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, c @ '\\')) | Some((_, c @ '$')) => {
+                        self.word_part(start, c)?;
+                    },
+                    Some(_) => {},
+                    None => return Err(Error::Unterminated(start, self.input.len())),
+                }
+            },
+            '$' if self.eat('(') => self.balanced(start, '(', ')')?,
+            '$' if self.eat('{') => self.balanced(start, '{', '}')?,
+            _ => {},
+        }
+        Ok(())
+    }
+
+    // Consume everything up to and including the `close` matching an
+    // already consumed `open`, skipping over quotes.
+    fn balanced(&mut self, start: usize, open: char, close: char)
+        -> Result<(), Error>
+    {
+        let mut depth = 1;
+        while let Some((_, c)) = self.chars.next() {
+            match c {
+                c if c == open => depth += 1,
+                c if c == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                },
+                c => self.word_part(start, c)?,
+            }
+        }
+        Err(Error::Unterminated(start, self.input.len()))
+    }
+
+    // Consume a language block, after its `{#`, returning the token of its
+    // header, with its text and closing brace pending.
+    fn block(&mut self, start: usize) -> Span<Token<'input>, Error> {
+        if posix::lex::is_strict() {
+            return Err(Error::Block(start, start + 2));
+        }
+
+        let header_start = self.position();
+        let token = if self.eat('!') {
+            let s = self.position();
+            while self.peek().map_or(false, |c| c != ';' && c != '\n') {
+                self.chars.next();
+            }
+            let header = &self.input[s..self.position()];
+            self.chars.next();
+            Token::Shebang(header.trim())
+        } else {
+            while self.peek().map_or(false, |c| !c.is_whitespace() && c != '}') {
+                self.chars.next();
+            }
+            Token::HashLang(&self.input[header_start..self.position()])
+        };
+        let header_end = self.position();
+        while self.peek().map_or(false, char::is_whitespace) {
+            self.chars.next();
+        }
+
+        // The text ends at the first `}` which isn't balanced by a `{`.
+        let text_start = self.position();
+        let mut depth = 0;
+        loop {
+            match self.chars.next() {
+                Some((_, '{')) => depth += 1,
+                Some((i, '}')) if depth == 0 => {
+                    let text = Token::Text(&self.input[text_start..i]);
+                    self.pending.push_back(Ok((text_start, text, i)));
+                    self.pending.push_back(Ok((i, Token::RBrace, i + 1)));
+                    return Ok((start, token, header_end));
+                },
+                Some((_, '}')) => depth -= 1,
+                Some(_) => {},
+                None => return Err(Error::Unterminated(start, self.input.len())),
+            }
+        }
+    }
+
+    fn lex(&mut self) -> Option<Span<Token<'input>, Error>> {
+        if let Some(span) = self.pending.pop_front() {
+            return Some(span);
+        }
+
+        loop {
+            let (start, c) = self.chars.next()?;
+            let token = match c {
+                ' ' | '\t' | '\r' => continue,
+                '\\' if self.peek() == Some('\n') => {
+                    self.chars.next();
+                    continue;
+                },
+                '#' => {
+                    while self.peek().map_or(false, |c| c != '\n') {
+                        self.chars.next();
+                    }
+                    continue;
+                },
+                '\n' | ';' if self.separated => continue,
+                '\n' => Token::Linefeed,
+                ';'  => Token::Semi,
+                '&' if self.eat('&') => Token::And,
+                '&'  => Token::Amper,
+                '|' if self.eat('|') => Token::Or,
+                '|'  => Token::Pipe,
+                '!' if self.separated && self.peek().map_or(true, char::is_whitespace) => {
+                    Token::Bang
+                },
+                '{' if self.eat('#') => return Some(self.block(start)),
+                '{'  => Token::LBrace,
+                '}'  => Token::RBrace,
+                '('  => Token::LParen,
+                ')'  => Token::RParen,
+                '>' if self.eat('>') => Token::DGreat,
+                '>' if self.eat('&') => Token::GreatAnd,
+                '>'  => Token::Great,
+                '<' if self.eat('&') => Token::LessAnd,
+                '<'  => Token::Less,
+                c => {
+                    return Some(self.word_part(start, c).and_then(|_| self.word(start)));
+                },
+            };
+            return Some(Ok((start, token, self.position())));
+        }
+    }
+}
+
+impl<'input> Iterator for Lexer<'input> {
+    type Item = Span<Token<'input>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let span = self.lex()?;
+        if let Ok((_, ref token, _)) = span {
+            self.separated = match token {
+                Token::Linefeed | Token::Semi | Token::Amper | Token::Pipe |
+                Token::And | Token::Or | Token::LBrace | Token::Bang |
+                Token::If | Token::Else | Token::While => true,
+                _ => false,
+            };
+            // Keywords may follow a block too, like `} else {`.
+            self.previous = if self.separated || *token == Token::RBrace {
+                (None, None)
+            } else {
+                (self.previous.1.take(), Some(token.clone()))
+            };
+        }
+        Some(span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(text: &str) -> Vec<Token> {
+        Lexer::new(text).map(|t| t.unwrap().1).collect()
+    }
+
+    #[test]
+    fn keywords() {
+        assert_eq!(vec![Token::Word("echo"), Token::Word("if"), Token::Word("in")],
+                   tokens("echo if in"));
+        assert_eq!(vec![Token::Let, Token::Word("x"), Token::Equals, Token::Word("1")],
+                   tokens("let x = 1"));
+        assert_eq!(vec![Token::RBrace, Token::Else, Token::LBrace],
+                   tokens("} else {"));
+    }
+
+    #[test]
+    fn separators() {
+        assert_eq!(vec![Token::Word("a"), Token::Linefeed, Token::Word("b"), Token::Semi],
+                   tokens("\n\n a # comment\n\n; b;;"));
+        assert_eq!(vec![Token::Word("a"), Token::Pipe, Token::Word("b"), Token::Or,
+                        Token::Bang, Token::Word("c")],
+                   tokens("a |\n b ||\n ! c"));
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(vec![Token::Word("\"a b\""), Token::Word("$(echo })"),
+                        Token::Word("'{'"), Token::IoNumber(2), Token::GreatAnd,
+                        Token::Word("1")],
+                   tokens("\"a b\" $(echo }) '{' 2>&1"));
+        assert!(Lexer::new("echo 'a").any(|t| t.is_err()));
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(vec![Token::HashLang("posix"), Token::Text("{ echo 1; }"),
+                        Token::RBrace],
+                   tokens("{#posix { echo 1; }}"));
+        assert_eq!(vec![Token::Shebang("/bin/sh"), Token::Text("echo 1"),
+                        Token::RBrace],
+                   tokens("{#!/bin/sh; echo 1}"));
+    }
+}
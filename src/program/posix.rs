@@ -101,7 +101,7 @@
 //! fi
 //!
 //! # Check if $1 is equal to "foo".
-//! if [ "$1" -eq "foo" ]; then
+//! if [ "$1" = "foo" ]; then
 //!     echo "bar"
 //! fi
 //! ```
@@ -116,30 +116,34 @@
 
 use std::{
     ffi::CString,
-    io::{Write, BufRead},
-    process::{self, Stdio},
-    fs::File,
-    os::unix::io::IntoRawFd,
-    env::set_var,
+    io::{self, Write, BufRead},
+    mem,
+    process,
+    fs::{self, File},
+    os::unix::io::{IntoRawFd, RawFd},
+    time::Instant,
 };
 use lalrpop_util::ParseError;
 use nix::{
-    sys::wait::WaitStatus,
-    unistd::Pid,
+    sys::wait::{waitpid, WaitStatus},
+    errno::Errno,
+    unistd::{close, fork, pipe, ForkResult, Pid},
 };
 use crate::{
     job::{Job, Jobs},
-    program::{Result, Error, IO},
+    program::{
+        Result, Error, IO, Run, exit, exit_status,
+        runtime::{Function, Runtime},
+    },
 };
 use self::ast::{Assignment, Redirect};
 
 #[cfg(feature = "shebang-block")]
 use {
-    std::io,
-    std::process::ExitStatus,
-    std::fs,
-    std::os::unix::fs::PermissionsExt,
     self::ast::Interpreter,
+    super::{
+        Program as ProgramTrait, ModernProgram, parse_primary, parse_alternate,
+    },
 };
 
 // Re-exports.
@@ -161,39 +165,54 @@ impl super::Program for Program {
 
     fn parse<R: BufRead>(mut reader: R) -> Result<Self> {
         let mut string = String::new();
-        if reader.read_to_string(&mut string).is_err() {
-            return Err(Error::Read);
+        reader.read_to_string(&mut string).map_err(Error::Read)?;
+
+        // A program of nothing but blank lines and comments does nothing.
+        if lex::Lexer::new(&string).all(|t| {
+            matches!(t, Ok((_, lex::Token::Linefeed, _)))
+        }) {
+            return Ok(ast::Program(vec![]));
         }
 
         let lexer = lex::Lexer::new(&string);
         let parser = parse::ProgramParser::new();
-        match parser.parse(&string, lexer) {
-            Ok(parsed) => Ok(parsed),
-            Err(e) => {
-                match e {
-                    ParseError::InvalidToken { location } => {
-                        eprintln!("invalid token found at {}", location);
+        parser.parse(&string, lexer).map_err(|e| {
+            let incomplete = match e {
+                ParseError::UnrecognizedEOF { .. } => true,
+                ParseError::User { error: lex::Error::Unterminated(..) } => true,
+                _ => false,
+            };
+            let (span, message) = match e {
+                ParseError::InvalidToken { location } => {
+                    ((location, location), "invalid token".into())
+                },
+                ParseError::UnrecognizedToken { token, expected } => {
+                    let (s, t, e) = token;
+                    ((s, e), format!("unexpected token {:?}, expecting one of: {}",
+                                     t, expected.join(", ")))
+                },
+                ParseError::UnrecognizedEOF { location, expected }=> {
+                    ((location, string.len()),
+                     format!("unexpected EOF, expecting one of: {}",
+                             expected.join(", ")))
+                }
+                ParseError::ExtraToken { token: (s, t, e) } => {
+                    ((s, e), format!("extra token {:?}", t))
+                }
+                ParseError::User { error } => match error {
+                    lex::Error::UnrecognizedChar(s, c, e) => {
+                        ((s, e), format!("unexpected character {}", c))
                     },
-                    ParseError::UnrecognizedToken { token, expected } => {
-                        let (s, t, e) = token;
-                        eprintln!("unexpected token {:?} found at {}-{}, expecting one of: {}",
-                                  t, s, e, expected.join(", "));
+                    lex::Error::Unterminated(s, e) => {
+                        ((s, e), "unterminated quote or expansion".into())
                     },
-                    ParseError::UnrecognizedEOF { location, expected }=> {
-                        eprintln!("unexpected EOF found at {}, expecting one of: {}",
-                                  location, expected.join(", "));
-                    }
-                    ParseError::ExtraToken { token: (i, t, _) } => {
-                        eprintln!("extra token {:?} found at {}", t, i);
-                    }
-                    ParseError::User { error } => {
-                        let lex::Error::UnrecognizedChar(s, c, e) = error;
-                        eprintln!("unexpected character {} found at {}-{}", c, s, e);
+                    lex::Error::Block(s, e) => {
+                        ((s, e), "language blocks aren't POSIX".into())
                     },
-                }
-                Err(Error::Parse)
-            }
-        }
+                },
+            };
+            Error::Parse { span, message, incomplete }
+        })
     }
 
     fn commands(&self) -> &[Box<Self::Command>] {
@@ -201,225 +220,553 @@ impl super::Program for Program {
     }
 }
 
-// TODO: lazy_static.
-// const BUILTINS: HashMap<&'static str, &'static Builtin> = HashMap::new(...);
-
 // The semantics of a single POSIX command.
 impl super::Command for Command {}
 
 impl super::Run for Command {
-    fn run(&self, background: bool, mut io: IO, jobs: Jobs) -> Result<WaitStatus> {
+    fn run(&self, background: bool, io: IO, jobs: Jobs, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        // Only the trace of this command is ours to report, the commands
+        // it runs report their own.
+        let depth = runtime.borrow().traced.len();
+        let status = self.execute(background, io, jobs, runtime.clone());
+        let traced = runtime.borrow_mut().traced.split_off(depth).pop();
+        let status = status?;
+
+        let mut state = runtime.borrow_mut();
+        state.status = exit_status(&status);
+
+        // Background commands haven't finished yet, so there's nothing to
+        // report about them.
+        if let (Some((line, start)), false) = (traced, background) {
+            eprintln!("{} ({:.3}s, status {})",
+                      line, start.elapsed().as_secs_f64(), state.status);
+        }
+
+        // With `errexit` any failing command exits the shell, unless it's
+        // being run as a condition. Compound commands only fail because of
+        // the commands inside them, which have already been checked.
+        match self {
+            Command::Simple(..) | Command::Subshell(_) |
+            Command::Pipeline(..) | Command::Lang(..) => {
+                if state.options.errexit &&
+                   state.conditional == 0 &&
+                   state.status != 0
+                {
+                    exit(state.status);
+                }
+            },
+            _ => {},
+        }
+
+        Ok(status)
+    }
+}
+
+impl Command {
+    fn execute(&self, background: bool, mut io: IO, jobs: Jobs, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
         #[allow(unreachable_patterns)]
         match *self {
             Command::Simple(ref assignments, ref words, ref redirects) => {
-                for Assignment(name, value) in assignments {
-                    set_var(name, value);
+                runtime.borrow_mut().substitution = None;
+                let mut fields = vec![];
+                for word in words {
+                    fields.append(&mut expand::fields(&word.0, &runtime)?);
                 }
-
-                for r in redirects {
-                    match r {
-                        Redirect::RW { n, filename, .. } => {
-                            let file = File::with_options()
-                                            .create(true)
-                                            .read(true)
-                                            .write(true)
-                                            .open(filename).unwrap();
-                            let fd = file.into_raw_fd();
-                            io.0[*n as usize] = fd;
-                        },
-                        Redirect::Read { n, filename, .. } => {
-                            let file = File::with_options()
-                                            .read(true)
-                                            .write(false)
-                                            .open(filename).unwrap();
-                            let fd = file.into_raw_fd();
-                            io.0[*n as usize] = fd;
-                        },
-                        Redirect::Write { n, filename, append, .. } => {
-                            // TODO: Clobber
-                            let file = File::with_options()
-                                            .create(true)
-                                            .read(false)
-                                            .write(true)
-                                            .append(*append)
-                                            .open(filename).unwrap();
-                            let fd = file.into_raw_fd();
-                            io.0[*n as usize] = fd;
-                        },
-                    };
+                let mut values = vec![];
+                for Assignment(name, value) in assignments {
+                    values.push((name.clone(), expand::string(value, &runtime)?));
                 }
 
-                let argv: Vec<CString> = words.iter().map(|w| {
-                    CString::new(&w.0 as &str)
-                        .expect("error in word UTF-8")
-                }).collect();
-
-                if let Some(command) = argv.clone().first() {
-                    match command.to_string_lossy().as_ref() {
-                        // TODO: IO for builtins.
-                        ":"    => builtin::Null::run(argv, jobs),
-                        "exit" => builtin::Exit::run(argv, jobs),
-                        "cd"   => builtin::Cd::run(argv, jobs),
-                        "jobs" => builtin::Jobs::run(argv, jobs),
-                        _ => {
-                            let id = (jobs.borrow().len() + 1).to_string();
-                            let mut job = Job::new(argv);
-                            if background {
-                                let status = job.fork(io).map_err(|_| Error::Runtime);
-                                if let Some(pid) = job.pid() {
-                                    eprintln!("[{}]\t{}", id, pid)
-                                }
-                                jobs.borrow_mut().push((id, job));
-                                status
-                            } else {
-                                job.fork_and_wait(io)
-                                   .map_err(|_| Error::Runtime)
-                            }
-                        },
-                    }
-                } else {
-                    Ok(WaitStatus::Exited(Pid::this(), 0))
-                }
+                simple(values, fields, redirects, background, io, jobs, runtime)
             },
             Command::Compound(ref commands) => {
                 // TODO: Need a way to run a set of commands as one in the
                 // background. Kinda like a subshell.
                 let mut last = WaitStatus::Exited(Pid::this(), 0);
                 for command in commands.iter() {
-                    last = command.run(false, io, jobs.clone())?;
+                    last = command.run(false, io, jobs.clone(), runtime.clone())?;
                 }
                 Ok(last)
             },
             Command::Not(ref command) => {
-                match command.run(false, io, jobs.clone()) {
-                    Ok(WaitStatus::Exited(p, c)) => {
+                match conditional(&runtime, || {
+                    command.run(false, io, jobs.clone(), runtime.clone())
+                })? {
+                    WaitStatus::Exited(p, c) => {
                         Ok(WaitStatus::Exited(p, (c == 0) as i32))
                     }
-                    Ok(s) => Ok(s),
-                    Err(_) => Err(Error::Runtime),
+                    s => Ok(s),
                 }
             },
             Command::And(ref left, ref right) => {
-                match left.run(false, io, jobs.clone()) {
-                    Ok(WaitStatus::Exited(_, c)) if c == 0 => {
-                        right.run(false, io, jobs.clone()).map_err(|_| Error::Runtime)
+                match conditional(&runtime, || {
+                    left.run(false, io, jobs.clone(), runtime.clone())
+                })? {
+                    WaitStatus::Exited(_, c) if c == 0 => {
+                        right.run(false, io, jobs.clone(), runtime.clone())
                     },
-                    Ok(s) => Ok(s),
-                    Err(_) => Err(Error::Runtime),
+                    s => Ok(s),
                 }
             },
             Command::Or(ref left, ref right) => {
-                match left.run(false, io, jobs.clone()) {
-                    Ok(WaitStatus::Exited(_, c)) if c != 0 => {
-                        right.run(false, io, jobs.clone()).map_err(|_| Error::Runtime)
+                match conditional(&runtime, || {
+                    left.run(false, io, jobs.clone(), runtime.clone())
+                })? {
+                    WaitStatus::Exited(_, c) if c != 0 => {
+                        right.run(false, io, jobs.clone(), runtime.clone())
+                    },
+                    s => Ok(s),
+                }
+            },
+            Command::If(ref cond, ref then, ref els) => {
+                match conditional(&runtime, || {
+                    cond.run(false, io, jobs.clone(), runtime.clone())
+                })? {
+                    WaitStatus::Exited(_, c) if c == 0 => {
+                        then.run(false, io, jobs.clone(), runtime.clone())
+                    },
+                    _ => match els {
+                        Some(els) => els.run(false, io, jobs.clone(), runtime.clone()),
+                        None => Ok(WaitStatus::Exited(Pid::this(), 0)),
                     },
-                    Ok(s) => Ok(s),
-                    Err(_) => Err(Error::Runtime),
                 }
             },
+            Command::While(ref cond, ref body) |
+            Command::Until(ref cond, ref body) => {
+                let until = matches!(self, Command::Until(..));
+                let mut last = WaitStatus::Exited(Pid::this(), 0);
+                loop {
+                    let status = conditional(&runtime, || {
+                        cond.run(false, io, jobs.clone(), runtime.clone())
+                    })?;
+                    if (exit_status(&status) == 0) == until {
+                        break;
+                    }
+                    last = body.run(false, io, jobs.clone(), runtime.clone())?;
+                }
+                Ok(last)
+            },
+            Command::Redirected(ref command, ref redirects) => {
+                let mut opened = vec![];
+                for r in redirects {
+                    let filename = expand::string(r.filename(), &runtime)?;
+                    let noclobber = runtime.borrow().options.noclobber;
+                    match redirect(r, &filename, noclobber, &mut io) {
+                        Ok(fd) => opened.extend(fd),
+                        Err(error) => {
+                            for fd in opened {
+                                close(fd).ok();
+                            }
+                            eprintln!("oursh: {}", Error::Redirect { filename, error });
+                            return Ok(WaitStatus::Exited(Pid::this(), 1));
+                        },
+                    }
+                }
+                let status = command.run(background, io, jobs, runtime);
+                // Close the files opened for this command, now that every
+                // command inside it is done with them.
+                for fd in opened {
+                    close(fd).ok();
+                }
+                status
+            },
             Command::Subshell(ref program) => {
                 // TODO #4: Run in a *subshell* ffs.
-                program.run(false, io, jobs)
+                program.run(false, io, jobs, runtime)
             },
             Command::Pipeline(ref left, ref right) => {
-                // TODO: This is obviously a temporary hack.
-                if let box Command::Simple(_assigns, lwords, _redirs) = left {
-                    let child = process::Command::new(&lwords[0].0)
-                        .args(lwords.iter().skip(1).map(|w| &w.0))
-                        .stdout(Stdio::piped())
-                        .spawn()
-                        .expect("error swawning pipeline process");
-
-                    let output = child.wait_with_output()
-                        .expect("error reading stdout");
-
-                    if let box Command::Simple(_assigns, rwords, _redirs) = right {
-                        let mut child = process::Command::new(&rwords[0].0)
-                            .args(rwords.iter().skip(1).map(|w| &w.0))
-                            .stdin(Stdio::piped())
-                            .spawn()
-                            .expect("error swawning pipeline process");
-
-                        {
-                            let stdin = child.stdin.as_mut()
-                                .expect("error opening stdin");
-                            stdin.write_all(&output.stdout)
-                                .expect("error writing to stdin");
-                        }
-
-                        child.wait()
-                            .expect("error waiting for piped command");
-                    }
-                }
-                Ok(WaitStatus::Exited(Pid::this(), 0))
+                pipeline(&**left, &**right, io, &jobs, &runtime)
             },
             Command::Background(ref command) => {
-                command.run(true, io, jobs.clone())
+                command.run(true, io, jobs.clone(), runtime.clone())?;
+                Ok(WaitStatus::Exited(Pid::this(), 0))
             },
             #[cfg(feature = "shebang-block")]
             Command::Lang(ref interpreter, ref text) => {
-                fn bridge(interpreter: &str, text: &str) -> io::Result<ExitStatus> {
-                    // TODO: Even for the Shebang interpretor, we shouldn't
-                    // create files like this.
-                    // XXX: Length is the worlds worst hash function.
-                    let bridgefile = format!("/tmp/.oursh_bridge-{}", text.len());
-                    {
-                        // TODO: Use our job interface without creating any
-                        // fucking files... The shebang isn't even a real
-                        // POSIX standard.
-                        let mut file = File::create(&bridgefile)?;
-                        let mut interpreter = interpreter.chars()
-                                                         .map(|c| c as u8)
-                                                         .collect::<Vec<u8>>();
-                        interpreter.insert(0, '!' as u8);
-                        interpreter.insert(0, '#' as u8);
-                        file.write_all(&interpreter)?;
-                        file.write_all(b"\n")?;
-                        let text = text.chars()
-                                       .map(|c| c as u8)
-                                       .collect::<Vec<u8>>();
-                        file.write_all(&text)?;
-
-                        let mut perms = fs::metadata(&bridgefile)?.permissions();
-                        perms.set_mode(0o777);
-                        fs::set_permissions(&bridgefile, perms)?;
-                    }
-                    // TODO #4: Suspend and restore raw mode.
-                    // TODO: Use our jobs, this will allow WaitStatus' too.
-                    process::Command::new(&bridgefile).spawn()?.wait()
-                }
-                // TODO: Pass text off to another parser.
-                match interpreter {
+                let block = match interpreter {
+                    // Our own languages run in this shell, sharing its state.
                     Interpreter::Primary => {
-                        unimplemented!()
-                    }
+                        let program = parse_primary(text.as_bytes())?;
+                        return program.run(background, io, jobs, runtime);
+                    },
+                    Interpreter::HashLang(ref name) if name == "posix" => {
+                        let program = Program::parse(text.as_bytes())?;
+                        return program.run(background, io, jobs, runtime);
+                    },
+                    Interpreter::HashLang(ref name) if name == "modern" => {
+                        let program = ModernProgram::parse(text.as_bytes())?;
+                        return program.run(background, io, jobs, runtime);
+                    },
                     Interpreter::Alternate => {
-                        bridge("/bin/sh", text).map_err(|_| Error::Read)?;
-                        Ok(WaitStatus::Exited(Pid::this(), 0))
+                        let program = parse_alternate(text.as_bytes())?;
+                        return program.run(background, io, jobs, runtime);
                     },
-                    Interpreter::HashLang(ref language) => {
-                        let interpreter = match language.as_str() {
-                            "ruby"   => "/usr/bin/env ruby",
-                            "node"   => "/usr/bin/env node",
-                            "python" => "/usr/bin/env python",
-                            "racket" => "/usr/bin/env racket",
-                            _        => return Err(Error::Read),
+                    Interpreter::HashLang(ref name) => {
+                        let text = interpolate(text, &runtime)?;
+                        // A named session keeps its interpreter running
+                        // between blocks.
+                        let (language, session) = match name.find('@') {
+                            Some(i) => (&name[..i], true),
+                            None => (&name[..], false),
                         };
-                        bridge(interpreter, text).map_err(|_| Error::Read)?;
-                        Ok(WaitStatus::Exited(Pid::this(), 0))
+                        match hashlang::get(language) {
+                            Some(language) if session => {
+                                return session::run(name, &language, &text, io);
+                            },
+                            Some(language) => bridge::hashlang(&language, &text)?,
+                            None => {
+                                eprintln!("oursh: {}: unknown language", language);
+                                return Ok(WaitStatus::Exited(Pid::this(), 127));
+                            },
+                        }
                     },
-                    Interpreter::Shebang(ref interpreter) => {
-                        bridge(interpreter, text).map_err(|_| Error::Read)?;
-                        Ok(WaitStatus::Exited(Pid::this(), 0))
+                    Interpreter::Shebang(ref line) => {
+                        let text = interpolate(text, &runtime)?;
+                        let mut words = vec![];
+                        for word in bridge::words(line) {
+                            words.append(&mut expand::fields(word, &runtime)?);
+                        }
+                        bridge::shebang(&words, &text)?
                     },
+                };
+                let io = block.io(io);
+                let status = start(block.job, background, io, &jobs, &runtime);
+                if let Some(fd) = block.fd {
+                    close(fd)?;
                 }
+                status
             },
             #[cfg(not(feature = "shebang-block"))]
             Command::Lang(_,_) => {
-                unimplemented!();
+                // The lexer already rejects blocks in this build, like it does
+                // with `--posix`, so this is only reached by a hand-made AST.
+                Err(Error::Runtime(nix::Error::Sys(Errno::EOPNOTSUPP)))
+            },
+        }
+    }
+}
+
+/// Run a simple command, given its expanded assignments and fields.
+///
+/// The command is traced, then its redirections are opened for it. The
+/// first field names a special builtin, a function, a builtin or a program,
+/// found in that order, and without any fields the assignments set shell
+/// variables.
+pub(crate) fn simple(values: Vec<(String, String)>, fields: Vec<String>,
+                     redirects: &[Redirect], background: bool,
+                     mut io: IO, jobs: Jobs, runtime: Runtime)
+    -> Result<WaitStatus>
+{
+    trace(&runtime, values.iter().map(|(name, value)| {
+        format!("{}={}", name, builtin::quote(value))
+    }).chain(fields.iter().map(|f| builtin::quote(f))));
+
+    let special = fields.first().map_or(false, |n| builtin::is_special(n));
+
+    // A redirection error is only fatal for special builtins, otherwise the
+    // command simply fails.
+    let mut opened = vec![];
+    for r in redirects {
+        let filename = expand::string(r.filename(), &runtime)?;
+        let noclobber = runtime.borrow().options.noclobber;
+        match redirect(r, &filename, noclobber, &mut io) {
+            Ok(fd) => opened.extend(fd),
+            Err(error) => {
+                for fd in opened {
+                    close(fd).ok();
+                }
+                let error = Error::Redirect { filename, error };
+                if special {
+                    return Err(error);
+                } else {
+                    eprintln!("oursh: {}", error);
+                    return Ok(WaitStatus::Exited(Pid::this(), 1));
+                }
             },
         }
     }
+
+    let status = invoke(values, fields, background, io, jobs, runtime);
+    // The command has its own copies of the files by now.
+    for fd in opened {
+        close(fd).ok();
+    }
+    status
+}
+
+// Run the fields of a simple command, once its redirections are in place.
+fn invoke(values: Vec<(String, String)>, fields: Vec<String>, background: bool,
+          io: IO, jobs: Jobs, runtime: Runtime)
+    -> Result<WaitStatus>
+{
+    let argv: Vec<CString> = fields.iter().map(|f| {
+        CString::new(f as &str)
+            .expect("error in word UTF-8")
+    }).collect();
+
+    let command = match fields.first() {
+        Some(command) => command,
+        None => {
+            let mut state = runtime.borrow_mut();
+            for (name, value) in values {
+                state.set(&name, &value);
+            }
+            let code = state.substitution.unwrap_or(0);
+            return Ok(WaitStatus::Exited(Pid::this(), code));
+        },
+    };
+
+    // Assignments before special builtins stay set, for functions and other
+    // builtins they only last for the command.
+    let special = builtin::is_special(command);
+    let function = runtime.borrow().functions.get(command).cloned();
+    if let (Some(function), false) = (function, special) {
+        let args = fields[1..].to_vec();
+        return assigned(&values, false, &runtime, || {
+            call(&function, args, io, jobs, runtime.clone())
+        });
+    }
+    if let Some(builtin) = builtin::get(command) {
+        return assigned(&values, special, &runtime, || {
+            builtin(argv, io, jobs, runtime.clone())
+        });
+    }
+
+    let mut job = Job::new(argv);
+    for (name, value) in values {
+        job.env(&name, &value);
+    }
+    start(job, background, io, &jobs, &runtime)
+}
+
+// Run `f` with the variables assigned, restoring them afterwards unless
+// they're kept.
+fn assigned<F>(values: &[(String, String)], keep: bool, runtime: &Runtime, f: F)
+    -> Result<WaitStatus>
+    where F: FnOnce() -> Result<WaitStatus>
+{
+    let saved: Vec<_> = values.iter().map(|(name, _)| {
+        (name.clone(), runtime.borrow().get(name))
+    }).collect();
+    for (name, value) in values {
+        runtime.borrow_mut().set(name, value);
+    }
+    let status = f();
+    if !keep {
+        let mut state = runtime.borrow_mut();
+        for (name, value) in saved {
+            match value {
+                Some(value) => state.set(&name, &value),
+                None => state.unset(&name),
+            }
+        }
+    }
+    status
+}
+
+/// Call a function, with its parameters and the positional parameters set
+/// to the arguments until it returns. Missing arguments are empty.
+pub(crate) fn call(function: &Function, args: Vec<String>,
+                   io: IO, jobs: Jobs, runtime: Runtime)
+    -> Result<WaitStatus>
+{
+    let params: Vec<_> = function.params.iter().enumerate().map(|(i, name)| {
+        (name.clone(), args.get(i).cloned().unwrap_or_default())
+    }).collect();
+    let args = mem::replace(&mut runtime.borrow_mut().args, args);
+    let status = assigned(&params, false, &runtime, || {
+        function.body.run(false, io, jobs, runtime.clone())
+    });
+    runtime.borrow_mut().args = args;
+    status
+}
+
+/// The text of a block, with its parameters expanded when the `interpolate`
+/// option is set.
+#[cfg(feature = "shebang-block")]
+fn interpolate(text: &str, runtime: &Runtime) -> Result<String> {
+    if runtime.borrow().options.interpolate {
+        expand::text(text, runtime)
+    } else {
+        Ok(text.into())
+    }
+}
+
+/// Start a job, waiting for it to finish unless it's run in the background,
+/// where it's added to the shell's jobs.
+pub(crate) fn start(mut job: Job, background: bool, io: IO, jobs: &Jobs, runtime: &Runtime)
+    -> Result<WaitStatus>
+{
+    if background {
+        let id = (jobs.borrow().len() + 1).to_string();
+        let status = job.fork(io)?;
+        if let Some(pid) = job.pid() {
+            eprintln!("[{}]\t{}", id, pid);
+            runtime.borrow_mut().background = Some(pid);
+        }
+        jobs.borrow_mut().push((id, job));
+        Ok(status)
+    } else {
+        Ok(job.fork_and_wait(io)?)
+    }
+}
+
+/// Run a pipeline of two commands, each in a subshell reading or writing its
+/// end of the pipe.
+pub(crate) fn pipeline<C: Run>(left: &C, right: &C, io: IO,
+                               jobs: &Jobs, runtime: &Runtime)
+    -> Result<WaitStatus>
+{
+    let (read, write) = pipe()?;
+    let children = subshell(left, IO([io.0[0], write, io.0[2]]), read, jobs, runtime)
+        .and_then(|left| {
+            subshell(right, IO([read, io.0[1], io.0[2]]), write, jobs, runtime)
+                .map(|right| (left, right))
+        });
+    close(read)?;
+    close(write)?;
+    let (left, right) = children?;
+
+    let lcode = exit_status(&waitpid(left, None)?);
+    let mut code = exit_status(&waitpid(right, None)?);
+    // With `pipefail` the last failure is the status.
+    if code == 0 && runtime.borrow().options.pipefail {
+        code = lcode;
+    }
+    Ok(WaitStatus::Exited(right, code))
+}
+
+/// Run a command in a forked copy of the shell, returning the child's pid.
+///
+/// The child closes `unused` (the other end of its pipe) first, and exits
+/// with the command's status.
+pub(crate) fn subshell<C: Run>(command: &C, io: IO, unused: RawFd,
+                               jobs: &Jobs, runtime: &Runtime)
+    -> Result<Pid>
+{
+    match fork()? {
+        ForkResult::Parent { child } => Ok(child),
+        ForkResult::Child => {
+            close(unused).ok();
+            let code = match command.run(false, io, jobs.clone(), runtime.clone()) {
+                Ok(status) => exit_status(&status),
+                Err(error) => {
+                    eprintln!("oursh: {}", error);
+                    error.status()
+                },
+            };
+            io::stdout().flush().ok();
+            process::exit(code);
+        },
+    }
+}
+
+/// Open the (expanded) file of a redirect, replacing the matching
+/// descriptor of `io`.
+///
+/// With `noclobber`, the `>` redirection won't overwrite an existing regular
+/// file, though `>|` still will.
+///
+/// Returns the descriptor of the opened file, which the caller closes once
+/// the command is done with it.
+pub(crate) fn redirect(redirect: &Redirect, filename: &str, noclobber: bool, io: &mut IO)
+    -> io::Result<Option<RawFd>>
+{
+    let (n, duplicate) = match *redirect {
+        Redirect::RW { n, .. } => (n, false),
+        Redirect::Read { n, duplicate, .. } |
+        Redirect::Write { n, duplicate, .. } => (n, duplicate),
+    };
+    // TODO: Descriptors above 2 need a wider table than `IO`, or to be
+    // duplicated in the child.
+    if n < 0 || n > 2 {
+        return Err(io::Error::new(io::ErrorKind::Other,
+                                  format!("file descriptor {} isn't supported", n)));
+    }
+
+    // `n>&m` and `n<&m` use the same file as `m`, and `n>&-` closes `n`.
+    if duplicate {
+        io.0[n as usize] = match filename.parse::<usize>() {
+            _ if filename == "-" => -1,
+            Ok(m) if m <= 2 => io.0[m],
+            _ => return Err(io::Error::from_raw_os_error(Errno::EBADF as i32)),
+        };
+        return Ok(None);
+    }
+
+    let file = match redirect {
+        Redirect::RW { .. } => {
+            File::with_options()
+                     .create(true)
+                     .read(true)
+                     .write(true)
+                     .open(filename)?
+        },
+        Redirect::Read { .. } => {
+            File::with_options()
+                     .read(true)
+                     .write(false)
+                     .open(filename)?
+        },
+        Redirect::Write { append, clobber, .. } => {
+            if noclobber && !clobber && !append {
+                if let Ok(metadata) = fs::metadata(filename) {
+                    if metadata.is_file() {
+                        return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                                  "cannot overwrite existing file"));
+                    }
+                }
+            }
+            File::with_options()
+                     .create(true)
+                     .read(false)
+                     .write(true)
+                     .truncate(!append)
+                     .append(*append)
+                     .open(filename)?
+        },
+    };
+    let fd = file.into_raw_fd();
+    io.0[n as usize] = fd;
+    Ok(Some(fd))
+}
+
+/// Run a command as a condition, where `errexit` is ignored.
+pub(crate) fn conditional<F>(runtime: &Runtime, f: F) -> Result<WaitStatus>
+    where F: FnOnce() -> Result<WaitStatus>
+{
+    runtime.borrow_mut().conditional += 1;
+    let status = f();
+    runtime.borrow_mut().conditional -= 1;
+    status
+}
+
+/// Write the (expanded and quoted) words of a command to STDERR for
+/// `xtrace`, after the expansion of `$PS4`.
+///
+/// Compound commands aren't written themselves, but the commands inside them
+/// are, including the conditions of `if`, `&&` and `||`.
+pub(crate) fn trace<I>(runtime: &Runtime, words: I)
+    where I: Iterator<Item = String>
+{
+    if !runtime.borrow().options.xtrace {
+        return;
+    }
+
+    let ps4 = runtime.borrow().get("PS4").unwrap_or_else(|| "+ ".into());
+    let ps4 = expand::string(&ps4, runtime).unwrap_or(ps4);
+    let line = format!("{}{}", ps4, words.collect::<Vec<_>>().join(" "));
+    // A single write, so the lines of a pipeline's commands aren't mixed.
+    io::stderr().write_all(format!("{}\n", line).as_bytes()).ok();
+
+    let mut state = runtime.borrow_mut();
+    if state.options.xtracetime {
+        state.traced.push((line, Instant::now()));
+    }
 }
 
 // Builtin functions for the POSIX language, like `exit` and `cd`.
@@ -428,9 +775,27 @@ pub mod builtin;
 // The POSIX AST data structures and helper functions.
 pub mod ast;
 
+// Expansion of the words of a command, like `$HOME` or `"$@"`.
+pub mod expand;
+
 // The custom LALRPOP lexer.
 pub mod lex;
 
+// Aliases, which the lexer substitutes for the first word of a command.
+pub mod alias;
+
+// Running `{#!...}` blocks with other interpreters.
+#[cfg(feature = "shebang-block")]
+pub mod bridge;
+
+// The interpreters of `{#lang ...}` blocks, registered with `lang`.
+#[cfg(feature = "shebang-block")]
+pub mod hashlang;
+
+// Long-running interpreters for `{#lang@name ...}` blocks.
+#[cfg(feature = "shebang-block")]
+pub mod session;
+
 // Following with the skiing analogy, the code inside here is black level.
 // Many of the issues in a grammar rule cause conflicts in seemingly unrelated
 // rules. Some issues are known to be harder to solve, and while LALRPOP does
@@ -0,0 +1,78 @@
+//! The aliases of the shell, set with `alias` and removed with `unalias`.
+//!
+//! Aliases are substituted by the lexer, for the first word of each command,
+//! so they're kept here rather than in the `Runtime`, which the lexer never
+//! sees.
+//!
+//! ```
+//! use oursh::program::posix::alias;
+//!
+//! alias::set("ll", "ls -l");
+//! assert_eq!(Some("ls -l"), alias::get("ll"));
+//! assert!(alias::remove("ll"));
+//! assert_eq!(None, alias::get("ll"));
+//! ```
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+thread_local! {
+    static ALIASES: RefCell<HashMap<String, &'static str>> = {
+        RefCell::new(HashMap::new())
+    };
+
+    // Each distinct alias value is leaked once, so the tokens lexed from it
+    // can outlive the input of any program.
+    static VALUES: RefCell<HashSet<&'static str>> = {
+        RefCell::new(HashSet::new())
+    };
+}
+
+/// Define an alias, replacing any existing alias of the same name.
+pub fn set(name: &str, value: &str) {
+    let value = VALUES.with(|values| {
+        let mut values = values.borrow_mut();
+        match values.get(value) {
+            Some(value) => *value,
+            None => {
+                let value: &'static str = Box::leak(value.into());
+                values.insert(value);
+                value
+            },
+        }
+    });
+    ALIASES.with(|aliases| aliases.borrow_mut().insert(name.into(), value));
+}
+
+/// The value of the named alias.
+pub fn get(name: &str) -> Option<&'static str> {
+    ALIASES.with(|aliases| aliases.borrow().get(name).cloned())
+}
+
+/// Remove the named alias, returning false if there wasn't one.
+pub fn remove(name: &str) -> bool {
+    ALIASES.with(|aliases| aliases.borrow_mut().remove(name).is_some())
+}
+
+/// Remove every alias.
+pub fn clear() {
+    ALIASES.with(|aliases| aliases.borrow_mut().clear());
+}
+
+/// Every alias, sorted by name.
+pub fn all() -> Vec<(String, &'static str)> {
+    let mut all: Vec<_> = ALIASES.with(|aliases| {
+        aliases.borrow().iter().map(|(n, v)| (n.clone(), *v)).collect()
+    });
+    all.sort();
+    all
+}
+
+/// Returns true for the names an alias may have, which can't contain any
+/// quotes, expansions or syntax.
+pub fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| {
+        c.is_alphanumeric() || "!%,-@_".contains(c)
+    })
+}
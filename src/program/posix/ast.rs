@@ -56,6 +56,45 @@ pub enum Command {
     /// ```
     Or(Box<Command>, Box<Command>),
 
+    /// Run the second command if the first succeeds, otherwise run the
+    /// optional third command. `elif` is simply a nested `If`.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// if test -f Cargo.toml; then cargo build; else make; fi
+    /// ```
+    If(Box<Command>, Box<Command>, Option<Box<Command>>),
+
+    /// Run the second command for as long as the first succeeds.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// while read -r line; do echo "$line"; done
+    /// ```
+    While(Box<Command>, Box<Command>),
+
+    /// Run the second command for as long as the first fails.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// until test -f /tmp/ready; do sleep 1; done
+    /// ```
+    Until(Box<Command>, Box<Command>),
+
+    /// Run a compound command with redirections, which apply to every
+    /// command inside it.
+    ///
+    /// ### Examples
+    ///
+    /// ```sh
+    /// while read -r line; do echo "$line"; done < Cargo.toml
+    /// { date; uptime; } > status.txt
+    /// ```
+    Redirected(Box<Command>, Vec<Redirect>),
+
     /// Run the inner **program** in a sub-shell environment.
     ///
     /// ### Examples
@@ -93,6 +132,15 @@ pub enum Command {
     /// {#ruby puts (Math.sqrt(32**2/57.2))}
     /// ```
     ///
+    /// The text ends at the first `}` which doesn't close a `{` inside it.
+    /// Text with unbalanced braces can instead end with a fence, given as
+    /// `<<WORD` after the interpreter.
+    ///
+    /// ```sh
+    /// {#!/usr/bin/env ruby; h = {a: {b: 1}}; puts h}
+    /// {#ruby <<END puts "}" END}
+    /// ```
+    ///
     /// ### Compatibility
     ///
     /// This is **non-POSIX**
@@ -101,10 +149,11 @@ pub enum Command {
     Lang(Interpreter, String),
 }
 
-/// A parsed word, already having gone through expansion.
-// TODO #8: How can we expand things like $1 or $? from the lexer?
-// TODO #8: This needs to handle escapes and all kinds of fun. We first
-//       need to decide on our custom Tokens and lexer.
+/// A parsed word, exactly as it was written.
+///
+/// Quotes, escapes and parameters are all kept in the word, and are only
+/// handled when the word is expanded, right before the command runs. See
+/// the [`expand`](super::expand) module.
 #[derive(Debug, Clone)]
 pub struct Word(pub String);
 
@@ -153,11 +202,53 @@ impl Redirect {
             // Redirect::Here { ref mut n, .. } => n,
         }
     }
+
+    pub fn filename(&self) -> &str {
+        match self {
+            Redirect::RW { ref filename, .. } => filename,
+            Redirect::Read { ref filename, .. } => filename,
+            Redirect::Write { ref filename, .. } => filename,
+        }
+    }
 }
 
+/// A variable assignment `name=value`, where the value is an unexpanded
+/// word.
 #[derive(Debug, Clone)]
 pub struct Assignment(pub String, pub String);
 
+impl Assignment {
+    /// Parse an assignment from a word, if the part before the first `=`
+    /// is a valid name.
+    ///
+    /// ```
+    /// use oursh::program::posix::ast::Assignment;
+    ///
+    /// assert!(Assignment::parse("PI=3.14").is_some());
+    /// assert!(Assignment::parse("--prefix=/usr").is_none());
+    /// assert!(Assignment::parse("'PI'=3.14").is_none());
+    /// ```
+    pub fn parse(word: &str) -> Option<Self> {
+        let i = word.find('=')?;
+        if is_name(&word[..i]) {
+            Some(Assignment(word[..i].into(), word[i+1..].into()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns true if the text is a valid variable name.
+pub fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+            chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+        },
+        _ => false,
+    }
+}
+
 impl Command {
     pub fn push(mut self, command: &Command) -> Self {
         match self {
@@ -0,0 +1,238 @@
+//! Running the text of a `{#!...}` or `{#lang ...}` block with another
+//! interpreter.
+//!
+//! The text is never written to a file. On Linux it's written to an
+//! anonymous memfd, and elsewhere to a pipe, which the interpreter reads as
+//! `/dev/fd/N`, or on its standard input. Only languages which need a file
+//! extension get a file, in a private temporary directory which is removed
+//! along with the job. The interpreter is run as a `Job`, so blocks can be
+//! piped, redirected and run in the background like any other command.
+//!
+//! Blocks see the shell's exported variables in their environment, like
+//! any other command. The words of the `#!` line are expanded, so
+//! `{#!python "$@"; ...}` passes on the positional parameters, and with the
+//! `interpolate` option the text is expanded like a here-document.
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    ffi::CString,
+    io::Write,
+    os::unix::{fs::{DirBuilderExt, OpenOptionsExt}, io::RawFd},
+    path::PathBuf,
+};
+use nix::unistd::{close, mkstemp};
+use crate::{
+    job::{Fd, IO, Job},
+    program::{Result, Error},
+};
+use super::hashlang::Language;
+
+#[cfg(target_os = "linux")]
+use {
+    nix::sys::memfd::{memfd_create, MemFdCreateFlag},
+    nix::unistd::{lseek, Whence},
+};
+
+#[cfg(not(target_os = "linux"))]
+use {
+    std::process,
+    nix::unistd::{fork, pipe, ForkResult},
+    nix::sys::wait::waitpid,
+};
+
+/// The job running a block, and the descriptor its text is read from.
+pub struct Block {
+    pub job: Job,
+    /// The descriptor of the text, which is left open for the job, so the
+    /// shell must close it once the job has been started.
+    pub fd: Option<RawFd>,
+    /// The text is read from the job's standard input.
+    pub stdin: bool,
+}
+
+impl Block {
+    /// The IO of the job, from the IO of the block.
+    pub fn io(&self, io: IO) -> IO {
+        match self.fd {
+            Some(fd) if self.stdin => IO([fd, io.0[1], io.0[2]]),
+            _ => io,
+        }
+    }
+}
+
+/// Run the text with the (expanded) words of a `#!` line.
+///
+/// The text is read like a script file given to the interpreter, after the
+/// interpreter's options and before any other words, which become the
+/// arguments of the block. For `env` the command it runs is part of the
+/// interpreter too, so `{#!/usr/bin/env python -u a b; ...}` runs
+/// `/usr/bin/env python -u /dev/fd/N a b`.
+pub fn shebang(words: &[String], text: &str) -> Result<Block> {
+    let fd = script(text)?;
+    let argv = arguments(words, &format!("/dev/fd/{}", fd));
+    Ok(Block {
+        job: Job::new(cstrings(argv)),
+        fd: Some(fd),
+        stdin: false,
+    })
+}
+
+/// Run the text with a registered language, see `hashlang`.
+pub fn hashlang(language: &Language, text: &str) -> Result<Block> {
+    match language.extension {
+        Some(ref extension) if !language.stdin => {
+            let directory = directory()?;
+            let file = directory.join(format!("block.{}", extension));
+            let mut job = Job::new(cstrings(language.arguments(&file.to_string_lossy())));
+            job.temporary(directory);
+            OpenOptions::new().write(true).create_new(true).mode(0o600)
+                .open(&file)
+                .and_then(|mut f| f.write_all(text.as_bytes()))
+                .map_err(Error::Block)?;
+            Ok(Block { job, fd: None, stdin: false })
+        },
+        _ => {
+            let fd = script(text)?;
+            let argv = language.arguments(&format!("/dev/fd/{}", fd));
+            Ok(Block {
+                job: Job::new(cstrings(argv)),
+                fd: Some(fd),
+                stdin: language.stdin,
+            })
+        },
+    }
+}
+
+fn cstrings(words: Vec<String>) -> Vec<CString> {
+    words.into_iter().map(|word| {
+        CString::new(word).expect("error in interpreter")
+    }).collect()
+}
+
+// Create a new directory only the user can use, with a name which can't be
+// guessed. The name is found by `mkstemp`, and `mkdir` fails rather than
+// reuse a directory someone else made in its place.
+fn directory() -> Result<PathBuf> {
+    let (fd, path) = mkstemp(&env::temp_dir().join("oursh-XXXXXX"))?;
+    close(fd)?;
+    fs::remove_file(&path)
+        .and_then(|_| fs::DirBuilder::new().mode(0o700).create(&path))
+        .map_err(Error::Block)?;
+    Ok(path)
+}
+
+/// Split a `#!` line into its words, which are separated by unquoted
+/// blanks, and still need to be expanded.
+pub fn words(line: &str) -> Vec<&str> {
+    let mut words = vec![];
+    let mut start = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c.is_whitespace() {
+            if let Some(start) = start.take() {
+                words.push(&line[start..i]);
+            }
+            continue;
+        }
+        start = start.or(Some(i));
+        match c {
+            '\\' => { chars.next(); },
+            '\'' => { chars.find(|&(_, c)| c == '\''); },
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => { chars.next(); },
+                        '"' => break,
+                        _ => {},
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+    words.extend(start.map(|start| &line[start..]));
+    words
+}
+
+// The arguments of the interpreter, with the script inserted after its
+// options.
+fn arguments(words: &[String], script: &str) -> Vec<String> {
+    let is_option = |word: &String| word.starts_with('-');
+    let mut i = words.len().min(1);
+    if words.first().map_or(false, |w| w == "env" || w.ends_with("/env")) {
+        while words.get(i).map_or(false, |w| is_option(w) || w.contains('=')) {
+            i += 1;
+        }
+        i = words.len().min(i + 1);
+    }
+    while words.get(i).map_or(false, is_option) {
+        i += 1;
+    }
+
+    let mut arguments = words[..i].to_vec();
+    arguments.push(script.into());
+    arguments.extend_from_slice(&words[i..]);
+    arguments
+}
+
+// An open descriptor to read the whole text from, which isn't closed on
+// `exec`.
+#[cfg(target_os = "linux")]
+fn script(text: &str) -> Result<RawFd> {
+    let name = CString::new("oursh-block").expect("error in name");
+    let fd = memfd_create(&name, MemFdCreateFlag::empty())?;
+    Fd(fd).write_all(text.as_bytes()).map_err(Error::Block)?;
+    lseek(fd, 0, Whence::SeekSet)?;
+    Ok(fd)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn script(text: &str) -> Result<RawFd> {
+    let (read, write) = pipe()?;
+    // The text is written by a grandchild of the shell, so a large block
+    // can't fill the pipe before the interpreter starts, and nobody needs
+    // to wait for the writer.
+    match fork()? {
+        ForkResult::Parent { child } => {
+            waitpid(child, None)?;
+        },
+        ForkResult::Child => {
+            close(read).ok();
+            if let Ok(ForkResult::Child) = fork() {
+                // An interpreter may exit before reading everything.
+                Fd(write).write_all(text.as_bytes()).ok();
+            }
+            process::exit(0);
+        },
+    }
+    close(write)?;
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments(line: &str) -> Vec<String> {
+        let words: Vec<_> = words(line).into_iter().map(String::from).collect();
+        super::arguments(&words, "-")
+    }
+
+    #[test]
+    fn split_words() {
+        assert_eq!(vec!["/bin/sh"], words("/bin/sh  "));
+        assert_eq!(vec!["python", "'a b'", "\"$@\"", "c\\ d"],
+                   words(" python 'a b' \"$@\" c\\ d"));
+        assert!(words("").is_empty());
+    }
+
+    #[test]
+    fn script_arguments() {
+        assert_eq!(vec!["/bin/sh", "-"], arguments("/bin/sh"));
+        assert_eq!(vec!["/bin/sh", "-e", "-", "a"], arguments("/bin/sh -e a"));
+        assert_eq!(vec!["python", "-", "a", "-b"], arguments("python a -b"));
+        assert_eq!(vec!["/usr/bin/env", "X=1", "python", "-u", "-", "a"],
+                   arguments("/usr/bin/env X=1 python -u a"));
+        assert_eq!(vec!["-"], arguments(""));
+    }
+}
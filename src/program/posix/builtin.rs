@@ -5,80 +5,544 @@
 //! in the `$PATH`.
 use std::{
     env,
-    process,
+    fs,
+    mem,
+    io::Write,
     ffi::CString,
+    path::PathBuf,
 };
 use nix::{
-    unistd::{chdir, Pid},
+    unistd::Pid,
     sys::wait::WaitStatus,
 };
 use crate::{
-    program::{Result, Error},
-    job::Jobs as JobsRef,
+    program::{
+        Result, Error, IO,
+        Program as ProgramTrait, Run, exit,
+        parse_primary,
+        posix::{Program, alias, ast},
+        runtime::{Runtime, Options},
+    },
+    job::{self, Job, Jobs as JobsRef},
 };
 
+/// Returns true for the special builtins of POSIX (3§2.14).
+///
+/// Errors in special builtins, including errors in their redirections, are
+/// fatal to a non-interactive shell.
+pub fn is_special(name: &str) -> bool {
+    match name {
+        "break" | ":" | "continue" | "." | "eval" | "exec" | "exit" |
+        "export" | "readonly" | "return" | "set" | "shift" | "times" |
+        "trap" | "unset" => true,
+        _ => false,
+    }
+}
+
+/// The function which runs a builtin, see `Builtin::run`.
+pub type Function = fn(Vec<CString>, IO, JobsRef, Runtime) -> Result<WaitStatus>;
+
+/// Find the builtin with the given name.
+pub fn get(name: &str) -> Option<Function> {
+    let builtin: Function = match name {
+        ":"       => Null::run,
+        "alias"   => Alias::run,
+        "unalias" => Unalias::run,
+        "." | "source" => Dot::run,
+        "eval"    => Eval::run,
+        "exec"    => Exec::run,
+        "exit"    => Exit::run,
+        "set"     => Set::run,
+        "export"  => Export::run,
+        "shift"   => Shift::run,
+        "cd"      => Cd::run,
+        "pushd"   => Pushd::run,
+        "popd"    => Popd::run,
+        "dirs"    => Dirs::run,
+        "jobs"    => Jobs::run,
+        "test" | "[" => Test::run,
+        "true"    => True::run,
+        "false"   => False::run,
+        "echo"    => Echo::run,
+        "printf"  => Printf::run,
+        "pwd"     => Pwd::run,
+        "read"    => Read::run,
+        "command" => Command::run,
+        "type"    => Type::run,
+        "hash"    => Hash::run,
+        "getopts" => Getopts::run,
+        "umask"   => Umask::run,
+        "ulimit"  => Ulimit::run,
+        "times"   => Times::run,
+        "json"    => Json::run,
+        #[cfg(feature = "shebang-block")]
+        "lang"    => Lang::run,
+        _ => return None,
+    };
+    Some(builtin)
+}
+
 /// A builtin is a custom shell command, often changing the state of the
 /// shell in some way.
 pub trait Builtin {
     /// Execute the shell builtin command, returning a retult of the
     /// completion.
-    fn run(argv: Vec<CString>, jobs: JobsRef) -> Result<WaitStatus>;
+    fn run(argv: Vec<CString>, io: IO, jobs: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>;
+}
+
+/// Quote a string so the shell reads it back as a single word.
+///
+/// ```
+/// use oursh::program::posix::builtin::quote;
+///
+/// assert_eq!("hello", quote("hello"));
+/// assert_eq!("'hello world'", quote("hello world"));
+/// assert_eq!("'it'\\''s'", quote("it's"));
+/// ```
+pub fn quote(text: &str) -> String {
+    let plain = !text.is_empty() && text.chars().all(|c| {
+        c.is_alphanumeric() || "_-+=/.,:@%^".contains(c)
+    });
+    if plain {
+        text.into()
+    } else {
+        format!("'{}'", text.replace('\'', "'\\''"))
+    }
 }
 
 /// Exit builtin, alternative to ctrl-d.
 pub struct Exit;
 
 impl Builtin for Exit {
-    fn run(argv: Vec<CString>, _: JobsRef) -> Result<WaitStatus> {
+    fn run(argv: Vec<CString>, _: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
         match argv.len() {
             0 => {
                 panic!("command name not passed in argv[0]");
             },
             1 => {
-                process::exit(0)
+                let status = runtime.borrow().status;
+                exit(status)
             },
             2 => {
                 if let Ok(n) = str::parse(argv[1].to_str().unwrap()) {
-                    process::exit(n)
+                    exit(n)
                 } else {
-                    process::exit(2)
+                    exit(2)
                 }
             },
             _ => {
-                eprintln!("too many arguments");
-                Ok(WaitStatus::Exited(Pid::this(), 1))
+                Err(Error::Builtin {
+                    name: "exit".into(),
+                    message: "too many arguments".into(),
+                })
             }
         }
     }
 }
 
-/// Change directory (`cd`) builtin.
-pub struct Cd;
+/// Alias definition (`alias`) builtin.
+///
+/// ```sh
+/// alias ll='ls -l'
+/// alias ll
+/// ```
+///
+/// Each `name=value` argument defines an alias, and each `name` prints the
+/// alias so it can be read again by the shell. Without any arguments every
+/// alias is printed. Aliases take effect for commands parsed after the
+/// `alias` is run, so not for the rest of the same line.
+pub struct Alias;
 
-impl Builtin for Cd {
-    fn run(argv: Vec<CString>, _: JobsRef) -> Result<WaitStatus> {
-        match argv.len() {
-            0 => {
-                panic!("command name not passed in argv[0]");
+impl Builtin for Alias {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, _: Runtime)
+        -> Result<WaitStatus>
+    {
+        let args: Vec<String> = argv.iter().skip(1).map(|a| {
+            a.to_string_lossy().into_owned()
+        }).collect();
+        let print = |name: &str, value: &str| format!("{}={}\n", name, quote(value));
+
+        if args.is_empty() {
+            let text: String = alias::all().iter().map(|(n, v)| print(n, v)).collect();
+            return Ok(output("alias", io, text.as_bytes()));
+        }
+
+        let mut text = String::new();
+        let mut code = 0;
+        for arg in &args {
+            match arg.find('=') {
+                Some(i) if alias::is_name(&arg[..i]) => {
+                    alias::set(&arg[..i], &arg[i + 1..]);
+                },
+                Some(i) => {
+                    writeln!(io.stderr(), "oursh: alias: {}: invalid alias name",
+                             &arg[..i]).ok();
+                    code = 1;
+                },
+                None => match alias::get(arg) {
+                    Some(value) => text += &print(arg, value),
+                    None => {
+                        writeln!(io.stderr(), "oursh: alias: {}: not found", arg).ok();
+                        code = 1;
+                    },
+                },
+            }
+        }
+
+        match output("alias", io, text.as_bytes()) {
+            WaitStatus::Exited(_, 0) => Ok(WaitStatus::Exited(Pid::this(), code)),
+            status => Ok(status),
+        }
+    }
+}
+
+/// Alias removal (`unalias`) builtin.
+///
+/// ```sh
+/// unalias ll
+/// unalias -a
+/// ```
+pub struct Unalias;
+
+impl Builtin for Unalias {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, _: Runtime)
+        -> Result<WaitStatus>
+    {
+        let args: Vec<String> = argv.iter().skip(1).map(|a| {
+            a.to_string_lossy().into_owned()
+        }).collect();
+
+        match args.first().map(|a| a.as_str()) {
+            Some("-a") => {
+                alias::clear();
+                return Ok(WaitStatus::Exited(Pid::this(), 0));
             },
-            1 => {
-                let home = match env::var("HOME") {
-                    Ok(path) => path,
-                    Err(_) => return Err(Error::Runtime),
-                };
-                chdir(home.as_str()).map(|_| WaitStatus::Exited(Pid::this(), 0))
-                          .map_err(|_| Error::Runtime)
+            None => {
+                writeln!(io.stderr(), "oursh: unalias: usage: unalias [-a] name...").ok();
+                return Ok(WaitStatus::Exited(Pid::this(), 2));
             },
+            _ => {},
+        }
+
+        let mut code = 0;
+        for name in args.iter().skip_while(|a| *a == "--") {
+            if !alias::remove(name) {
+                writeln!(io.stderr(), "oursh: unalias: {}: not found", name).ok();
+                code = 1;
+            }
+        }
+        Ok(WaitStatus::Exited(Pid::this(), code))
+    }
+}
+
+/// Shell options and positional parameters (`set`) builtin.
+///
+/// ```sh
+/// set -euo pipefail
+/// set +x
+/// set -- a b c
+/// ```
+///
+/// Without arguments every variable is printed, and `set -o` or `set +o`
+/// print the current options.
+pub struct Set;
+
+impl Builtin for Set {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let args: Vec<String> = argv.iter().skip(1).map(|a| {
+            a.to_string_lossy().into_owned()
+        }).collect();
+
+        if args.is_empty() {
+            let mut text = String::new();
+            for (name, value) in runtime.borrow().variables() {
+                text += &format!("{}={}\n", name, quote(&value));
+            }
+            return Ok(output("set", io, text.as_bytes()));
+        }
+
+        let mut state = runtime.borrow_mut();
+        let mut positional = None;
+        let mut i = 0;
+        while i < args.len() {
+            let arg = &args[i];
+            i += 1;
+            match arg.as_str() {
+                "--" => {
+                    positional = Some(args[i..].to_vec());
+                    break;
+                },
+                "-" => {
+                    state.options.xtrace = false;
+                    state.options.verbose = false;
+                    if i < args.len() {
+                        positional = Some(args[i..].to_vec());
+                    }
+                    break;
+                },
+                _ if arg.len() > 1 && (arg.starts_with('-') ||
+                                       arg.starts_with('+')) => {
+                    let value = arg.starts_with('-');
+                    for flag in arg[1..].chars() {
+                        let name = if flag == 'o' {
+                            // Each `o` takes the name of the option as
+                            // the next argument, as in `set -euo pipefail`.
+                            match args.get(i) {
+                                Some(name) => {
+                                    i += 1;
+                                    name.as_str()
+                                },
+                                None => {
+                                    let text = print_options(&state.options,
+                                                             value);
+                                    output("set", io, text.as_bytes());
+                                    continue;
+                                },
+                            }
+                        } else {
+                            match Options::name(flag) {
+                                Some(name) => name,
+                                None => return Err(Error::Builtin {
+                                    name: "set".into(),
+                                    message: format!("{}{}: invalid option",
+                                                     &arg[..1], flag),
+                                }),
+                            }
+                        };
+                        match state.options.get_mut(name) {
+                            Some(option) => *option = value,
+                            None => return Err(Error::Builtin {
+                                name: "set".into(),
+                                message: format!("{}: invalid option name",
+                                                 name),
+                            }),
+                        }
+                    }
+                },
+                _ => {
+                    positional = Some(args[i - 1..].to_vec());
+                    break;
+                },
+            }
+        }
+
+        if let Some(args) = positional {
+            state.args = args;
+        }
+        Ok(WaitStatus::Exited(Pid::this(), 0))
+    }
+}
+
+// Print the options for `set -o`, or as commands which restore them for
+// `set +o`.
+fn print_options(options: &Options, human: bool) -> String {
+    let mut text = String::new();
+    for (name, _) in Options::FLAGS {
+        let value = options.get(name).unwrap_or(false);
+        if human {
+            let value = if value { "on" } else { "off" };
+            text += &format!("{:<15} {}\n", name, value);
+        } else {
+            let value = if value { '-' } else { '+' };
+            text += &format!("set {}o {}\n", value, name);
+        }
+    }
+    text
+}
+
+/// Positional parameter shifting builtin.
+///
+/// Removes the first `n` (default 1) positional parameters, so `$n+1`
+/// becomes `$1`.
+pub struct Shift;
+
+impl Builtin for Shift {
+    fn run(argv: Vec<CString>, _: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let error = |message: String| Error::Builtin {
+            name: "shift".into(),
+            message,
+        };
+
+        let n = match argv.len() {
+            0 | 1 => 1,
             2 => {
-                chdir(&*argv[1].to_string_lossy().as_ref())
-                    .map(|_| WaitStatus::Exited(Pid::this(), 1))
-                    .map_err(|_| Error::Runtime)
+                let arg = argv[1].to_string_lossy();
+                arg.parse::<usize>()
+                   .map_err(|_| error(format!("{}: numeric argument required", arg)))?
             },
-            _ => {
-                eprintln!("too many arguments");
-                Ok(WaitStatus::Exited(Pid::this(), 1))
+            _ => return Err(error("too many arguments".into())),
+        };
+
+        let mut state = runtime.borrow_mut();
+        if n > state.args.len() {
+            return Err(error(format!("{}: can't shift that many", n)));
+        }
+        state.args.drain(..n);
+        Ok(WaitStatus::Exited(Pid::this(), 0))
+    }
+}
+
+/// Variable exporting (`export`) builtin.
+///
+/// ```sh
+/// export EDITOR=vi PAGER
+/// export -p
+/// ```
+///
+/// Each variable, assigned first when given as `name=value`, is put in the
+/// environment of the commands run after it. Without any names, or with
+/// `-p`, every exported variable is printed so it can be read again by the
+/// shell.
+pub struct Export;
+
+impl Builtin for Export {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let args: Vec<String> = argv.iter().skip(1).map(|a| {
+            a.to_string_lossy().into_owned()
+        }).skip_while(|a| a == "-p").collect();
+
+        if args.is_empty() {
+            let mut variables: Vec<_> = env::vars_os().map(|(n, v)| {
+                (n.to_string_lossy().into_owned(), v.to_string_lossy().into_owned())
+            }).collect();
+            variables.sort();
+            let text: String = variables.iter().map(|(name, value)| {
+                format!("export {}={}\n", name, quote(value))
+            }).collect();
+            return Ok(output("export", io, text.as_bytes()));
+        }
+
+        let mut state = runtime.borrow_mut();
+        for arg in &args {
+            let (name, value) = match arg.find('=') {
+                Some(i) => (&arg[..i], Some(&arg[i + 1..])),
+                None => (&arg[..], None),
+            };
+            if !ast::is_name(name) {
+                return Err(Error::Builtin {
+                    name: "export".into(),
+                    message: format!("{}: invalid variable name", name),
+                });
+            }
+            if let Some(value) = value {
+                state.set(name, value);
             }
+            state.export(name);
         }
+        Ok(WaitStatus::Exited(Pid::this(), 0))
+    }
+}
+
+/// Script sourcing builtin, `.` or `source`.
+///
+/// Runs the commands of a file in the current shell, so they can change its
+/// variables and options. Names without a `/` are searched for in `$PATH`,
+/// and any extra arguments become the positional parameters while the file
+/// runs.
+pub struct Dot;
+
+impl Builtin for Dot {
+    fn run(argv: Vec<CString>, io: IO, jobs: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let name = argv[0].to_string_lossy().into_owned();
+        let error = |message: String| Error::Builtin {
+            name: name.clone(),
+            message,
+        };
+
+        let filename = match argv.get(1) {
+            Some(filename) => filename.to_string_lossy().into_owned(),
+            None => return Err(error("filename argument required".into())),
+        };
+        let path = find_file(&filename)
+            .ok_or_else(|| error(format!("{}: not found", filename)))?;
+        let text = fs::read_to_string(&path)
+            .map_err(|e| error(format!("{}: {}", filename, e)))?;
+        let program = Program::parse(text.as_bytes())?;
+
+        // Arguments replace the positional parameters until the file is
+        // done.
+        let args = if argv.len() > 2 {
+            let args = argv[2..].iter().map(|a| {
+                a.to_string_lossy().into_owned()
+            }).collect();
+            Some(mem::replace(&mut runtime.borrow_mut().args, args))
+        } else {
+            None
+        };
+
+        let status = program.run(false, io, jobs, runtime.clone());
+
+        if let Some(args) = args {
+            runtime.borrow_mut().args = args;
+        }
+        status
+    }
+}
+
+// Find a file to source, searching `$PATH` when the name has no `/`.
+fn find_file(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|file| file.is_file())
+}
+
+/// Argument evaluation builtin.
+///
+/// Joins its arguments with spaces, then parses and runs them as a program
+/// in the current shell.
+pub struct Eval;
+
+impl Builtin for Eval {
+    fn run(argv: Vec<CString>, io: IO, jobs: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let text = argv.iter().skip(1).map(|a| {
+            a.to_string_lossy()
+        }).collect::<Vec<_>>().join(" ");
+        let program = parse_primary(text.as_bytes())?;
+        program.run(false, io, jobs, runtime)
+    }
+}
+
+/// Process replacement builtin.
+///
+/// With a command, the shell is replaced by it, keeping any redirections.
+/// Without one, the redirections are applied to the shell itself, for all
+/// the commands which follow.
+pub struct Exec;
+
+impl Builtin for Exec {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, _: Runtime)
+        -> Result<WaitStatus>
+    {
+        // Without a command the redirections stay in place, and their files
+        // are still closed by the simple command which opened them.
+        if argv.len() < 2 {
+            io.dup()?;
+            return Ok(WaitStatus::Exited(Pid::this(), 0));
+        }
+        io.dup_and_close()?;
+
+        let job = Job::new(argv[1..].to_vec());
+        let error = job.exec().unwrap_err();
+        eprintln!("oursh: exec: {}: {}", argv[1].to_string_lossy(), error);
+        exit(job::status(&error));
     }
 }
 
@@ -86,7 +550,7 @@ impl Builtin for Cd {
 pub struct Null;
 
 impl Builtin for Null {
-    fn run(_: Vec<CString>, _: JobsRef) -> Result<WaitStatus> {
+    fn run(_: Vec<CString>, _: IO, _: JobsRef, _: Runtime) -> Result<WaitStatus> {
         Ok(WaitStatus::Exited(Pid::this(), 0))
     }
 }
@@ -95,13 +559,160 @@ impl Builtin for Null {
 pub struct Jobs;
 
 impl Builtin for Jobs {
-    fn run(_: Vec<CString>, jobs: JobsRef) -> Result<WaitStatus> {
+    fn run(_: Vec<CString>, io: IO, jobs: JobsRef, _: Runtime) -> Result<WaitStatus> {
+        let mut text = String::new();
         for (id, job) in jobs.borrow().iter() {
             if let Some(pid) = job.pid() {
-                println!("[{}]\t{}\t\t{}",
-                         id, pid, job.body());
+                text += &format!("[{}]\t{}\t\t{}\n",
+                                 id, pid, job.body());
             }
         }
+        Ok(output("jobs", io, text.as_bytes()))
+    }
+}
+
+/// Success builtin, always exits with 0.
+pub struct True;
+
+impl Builtin for True {
+    fn run(_: Vec<CString>, _: IO, _: JobsRef, _: Runtime) -> Result<WaitStatus> {
         Ok(WaitStatus::Exited(Pid::this(), 0))
     }
 }
+
+/// Failure builtin, always exits with 1.
+pub struct False;
+
+impl Builtin for False {
+    fn run(_: Vec<CString>, _: IO, _: JobsRef, _: Runtime) -> Result<WaitStatus> {
+        Ok(WaitStatus::Exited(Pid::this(), 1))
+    }
+}
+
+/// Argument printing builtin, with the escapes of XSI `echo`.
+///
+/// Backslash escapes like `\t` or `\0101` are always expanded, and `\c`
+/// stops all further output. Like `dash`, a first argument of `-n` leaves
+/// off the trailing newline, and no other options are recognized.
+pub struct Echo;
+
+impl Builtin for Echo {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, _: Runtime)
+        -> Result<WaitStatus>
+    {
+        let mut args = argv.iter().skip(1).peekable();
+        let newline = if args.peek().map(|a| a.as_bytes()) == Some(b"-n") {
+            args.next();
+            false
+        } else {
+            true
+        };
+
+        let mut bytes = vec![];
+        let mut stopped = false;
+        for (i, arg) in args.enumerate() {
+            if i > 0 {
+                bytes.push(b' ');
+            }
+            if !printf::escape(&arg.to_string_lossy(), true, &mut bytes) {
+                stopped = true;
+                break;
+            }
+        }
+        if newline && !stopped {
+            bytes.push(b'\n');
+        }
+        Ok(output("echo", io, &bytes))
+    }
+}
+
+/// Working directory printing builtin.
+///
+/// With `-L` (the default) the logical directory from `$PWD` is printed,
+/// which may go through symbolic links, and with `-P` the physical one.
+pub struct Pwd;
+
+impl Builtin for Pwd {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let mut logical = true;
+        for arg in argv.iter().skip(1) {
+            let arg = arg.to_string_lossy();
+            match arg.as_ref() {
+                "-L" => logical = true,
+                "-P" => logical = false,
+                _ => {
+                    writeln!(io.stderr(), "oursh: pwd: {}: invalid option", arg).ok();
+                    return Ok(WaitStatus::Exited(Pid::this(), 2));
+                },
+            }
+        }
+
+        let path = if logical {
+            runtime.borrow().pwd()
+        } else {
+            env::current_dir()
+        };
+        match path {
+            Ok(path) => {
+                let text = format!("{}\n", path.display());
+                Ok(output("pwd", io, text.as_bytes()))
+            },
+            Err(e) => {
+                writeln!(io.stderr(), "oursh: pwd: {}", e).ok();
+                Ok(WaitStatus::Exited(Pid::this(), 1))
+            },
+        }
+    }
+}
+
+// Write the output of a builtin, which fails with 1 when it can't be
+// written, for example to a closed pipe.
+fn output(name: &str, io: IO, bytes: &[u8]) -> WaitStatus {
+    match io.stdout().write_all(bytes) {
+        Ok(()) => WaitStatus::Exited(Pid::this(), 0),
+        Err(e) => {
+            writeln!(io.stderr(), "oursh: {}: write error: {}", name, e).ok();
+            WaitStatus::Exited(Pid::this(), 1)
+        },
+    }
+}
+
+// The `printf` builtin's format strings and escapes.
+pub mod printf;
+pub use self::printf::Printf;
+
+// The `test` builtin's conditional expressions.
+pub mod test;
+pub use self::test::Test;
+
+// The `read` builtin's line reading and field splitting.
+pub mod read;
+pub use self::read::Read;
+
+// The `cd` builtin and the directory stack.
+pub mod cd;
+pub use self::cd::{Cd, Pushd, Popd, Dirs};
+
+// The `command`, `type` and `hash` builtins, which find commands.
+pub mod command;
+pub use self::command::{Command, Type, Hash};
+
+// The `json` builtin, which binds the JSON output of a command to variables.
+pub mod json;
+pub use self::json::Json;
+
+// The `lang` builtin, which registers the interpreters of language blocks.
+#[cfg(feature = "shebang-block")]
+pub mod lang;
+#[cfg(feature = "shebang-block")]
+pub use self::lang::Lang;
+
+// The `getopts` builtin's option parsing.
+pub mod getopts;
+pub use self::getopts::Getopts;
+
+// The `umask`, `ulimit` and `times` builtins of the shell's own process.
+pub mod resource;
+pub use self::resource::{Umask, Ulimit, Times};
@@ -0,0 +1,368 @@
+//! The `cd` builtin, and the directory stack of `pushd`, `popd` and `dirs`.
+//!
+//! Directories are logical by default, so `cd` keeps `$PWD` naming the
+//! path through any symbolic links, and `cd ..` goes back up that path
+//! rather than to the parent of the link's target. The `-P` option uses the
+//! physical directory instead.
+use std::{
+    env,
+    ffi::CString,
+    io::Write,
+    path::{Component, Path, PathBuf},
+};
+use nix::{
+    unistd::{chdir, Pid},
+    sys::wait::WaitStatus,
+};
+use crate::{
+    program::{Result, IO, runtime::Runtime},
+    job::Jobs as JobsRef,
+};
+use super::{Builtin, output};
+
+/// Change directory (`cd`) builtin.
+///
+/// ```sh
+/// cd
+/// cd -P /tmp
+/// cd -
+/// ```
+///
+/// Without a directory `cd` goes to `$HOME`, and `cd -` goes back to
+/// `$OLDPWD`, printing it. Relative directories are searched for in each
+/// directory of `$CDPATH`, unless they start with `.` or `..`.
+pub struct Cd;
+
+impl Builtin for Cd {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let args = strings(&argv);
+        let (physical, operands) = match options(&args) {
+            Ok(parsed) => parsed,
+            Err(message) => return Ok(failure(io, "cd", &message, 2)),
+        };
+
+        let (operand, print) = match operands {
+            [] => match runtime.borrow().get("HOME").filter(|h| !h.is_empty()) {
+                Some(home) => (home, false),
+                None => return Ok(failure(io, "cd", "HOME not set", 1)),
+            },
+            [dash] if dash == "-" => match runtime.borrow().get("OLDPWD") {
+                Some(old) => (old, true),
+                None => return Ok(failure(io, "cd", "OLDPWD not set", 1)),
+            },
+            [operand] => (operand.clone(), false),
+            _ => return Ok(failure(io, "cd", "too many arguments", 1)),
+        };
+
+        match change(&operand, physical, true, &runtime) {
+            Ok((path, found)) if print || found => {
+                let text = format!("{}\n", path.display());
+                Ok(output("cd", io, text.as_bytes()))
+            },
+            Ok(_) => Ok(WaitStatus::Exited(Pid::this(), 0)),
+            Err(message) => Ok(failure(io, "cd", &message, 1)),
+        }
+    }
+}
+
+/// Push a directory onto the directory stack (`pushd`) builtin.
+///
+/// ```sh
+/// pushd /tmp
+/// pushd
+/// pushd +2
+/// ```
+///
+/// With a directory, the current directory is pushed onto the stack and the
+/// shell changes to the given one. Without one the top two directories are
+/// swapped, and `+n` or `-n` rotate the stack until the nth directory from
+/// the left or right is on top. The stack is printed afterwards, like
+/// `dirs`.
+pub struct Pushd;
+
+impl Builtin for Pushd {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let args = strings(&argv);
+        let mut stack = stack(&runtime);
+        let result = match args.as_slice() {
+            [] if stack.len() < 2 => Err("no other directory".into()),
+            [] => {
+                stack.swap(0, 1);
+                visit(stack, &runtime)
+            },
+            [n] if is_index(n) => index(n, stack.len()).and_then(|n| {
+                stack.rotate_left(n);
+                visit(stack, &runtime)
+            }),
+            [dir] => change(dir, false, true, &runtime).map(|_| {
+                runtime.borrow_mut().directories.insert(0, stack.remove(0));
+            }),
+            _ => Err("too many arguments".into()),
+        };
+
+        match result {
+            Ok(()) => Ok(print(&[], io, &runtime)),
+            Err(message) => Ok(failure(io, "pushd", &message, 1)),
+        }
+    }
+}
+
+/// Pop a directory off of the directory stack (`popd`) builtin.
+///
+/// ```sh
+/// popd
+/// popd +1
+/// ```
+///
+/// The top of the stack is removed and the shell changes to the next
+/// directory, or with `+n` or `-n` the nth directory from the left or right
+/// is removed instead. The stack is printed afterwards, like `dirs`.
+pub struct Popd;
+
+impl Builtin for Popd {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let args = strings(&argv);
+        let mut stack = stack(&runtime);
+        let n = match args.as_slice() {
+            [] => Ok(0),
+            [n] if is_index(n) => index(n, stack.len()),
+            [arg] => Err(format!("{}: invalid argument", arg)),
+            _ => Err("too many arguments".into()),
+        };
+
+        let result = n.and_then(|n| {
+            if stack.len() < 2 {
+                Err("directory stack empty".into())
+            } else if n == 0 {
+                stack.remove(0);
+                visit(stack, &runtime)
+            } else {
+                runtime.borrow_mut().directories.remove(n - 1);
+                Ok(())
+            }
+        });
+
+        match result {
+            Ok(()) => Ok(print(&[], io, &runtime)),
+            Err(message) => Ok(failure(io, "popd", &message, 1)),
+        }
+    }
+}
+
+/// Print the directory stack (`dirs`) builtin.
+///
+/// The current directory is printed first, followed by the stack from its
+/// top. Directories under `$HOME` are shortened to start with `~`, unless
+/// `-l` is given. With `-p` each directory is printed on its own line, and
+/// with `-v` each is numbered too. The stack is emptied with `-c`.
+pub struct Dirs;
+
+impl Builtin for Dirs {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        Ok(print(&strings(&argv), io, &runtime))
+    }
+}
+
+// Print the directory stack for `dirs`, given its arguments.
+fn print(args: &[String], io: IO, runtime: &Runtime) -> WaitStatus {
+    let (mut clear, mut long, mut lines, mut numbered) = (false, false, false, false);
+    for arg in args {
+        let flags = match arg.get(1..) {
+            Some(flags) if arg.starts_with('-') && !flags.is_empty() => flags,
+            _ => return failure(io, "dirs", &format!("{}: invalid argument", arg), 2),
+        };
+        for flag in flags.chars() {
+            match flag {
+                'c' => clear = true,
+                'l' => long = true,
+                'p' => lines = true,
+                'v' => numbered = true,
+                c => return failure(io, "dirs", &format!("-{}: invalid option", c), 2),
+            }
+        }
+    }
+    if clear {
+        runtime.borrow_mut().directories.clear();
+        return WaitStatus::Exited(Pid::this(), 0);
+    }
+
+    let home = runtime.borrow().get("HOME").filter(|h| !h.is_empty());
+    let names = stack(runtime).into_iter().map(|path| {
+        match home.as_ref().and_then(|h| path.strip_prefix(h).ok()) {
+            Some(rest) if !long && rest.as_os_str().is_empty() => "~".into(),
+            Some(rest) if !long => Path::new("~").join(rest).display().to_string(),
+            _ => path.display().to_string(),
+        }
+    });
+
+    let mut text = String::new();
+    for (i, name) in names.enumerate() {
+        if numbered {
+            text += &format!("{:2}  {}\n", i, name);
+        } else if lines {
+            text += &format!("{}\n", name);
+        } else {
+            text += &format!("{}{}", if i == 0 { "" } else { " " }, name);
+        }
+    }
+    if !lines && !numbered {
+        text.push('\n');
+    }
+    output("dirs", io, text.as_bytes())
+}
+
+/// Change to the directory named by `operand`, searching `$CDPATH` when
+/// `search` is true, and update `$PWD` and `$OLDPWD`.
+///
+/// Returns the new directory, and whether it was found with `$CDPATH`.
+fn change(operand: &str, physical: bool, search: bool, runtime: &Runtime)
+    -> std::result::Result<(PathBuf, bool), String>
+{
+    let mut path = PathBuf::from(operand);
+    let mut found = false;
+    let dotted = match Path::new(operand).components().next() {
+        Some(Component::CurDir) | Some(Component::ParentDir) => true,
+        _ => false,
+    };
+
+    if search && path.is_relative() && !dotted {
+        let cdpath = runtime.borrow().get("CDPATH").unwrap_or_default();
+        for prefix in cdpath.split(':').filter(|_| !cdpath.is_empty()) {
+            let candidate = Path::new(if prefix.is_empty() { "." } else { prefix })
+                .join(operand);
+            if candidate.is_dir() {
+                path = candidate;
+                found = !prefix.is_empty();
+                break;
+            }
+        }
+    }
+
+    let old = runtime.borrow().pwd().ok();
+    if !physical {
+        if let (true, Some(old)) = (path.is_relative(), &old) {
+            path = old.join(path);
+        }
+        path = normalize(&path);
+    }
+
+    chdir(&path).map_err(|e| format!("{}: {}", operand, e))?;
+    if physical {
+        path = env::current_dir().map_err(|e| e.to_string())?;
+    }
+
+    let mut state = runtime.borrow_mut();
+    if let Some(old) = old {
+        state.set("OLDPWD", &old.to_string_lossy());
+    }
+    state.set("PWD", &path.to_string_lossy());
+    Ok((path, found))
+}
+
+// Change to the first directory of `stack`, keeping the rest of it as the
+// directory stack.
+fn visit(mut stack: Vec<PathBuf>, runtime: &Runtime)
+    -> std::result::Result<(), String>
+{
+    let top = stack.remove(0);
+    change(&top.to_string_lossy(), false, false, runtime)?;
+    runtime.borrow_mut().directories = stack;
+    Ok(())
+}
+
+// The current directory followed by the directory stack.
+fn stack(runtime: &Runtime) -> Vec<PathBuf> {
+    let state = runtime.borrow();
+    let current = state.pwd().unwrap_or_else(|_| PathBuf::from("."));
+    let mut stack = vec![current];
+    stack.extend(state.directories.iter().cloned());
+    stack
+}
+
+// Remove `.` and `..` components from a path without reading any links.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => { normal.pop(); },
+            c => normal.push(c),
+        }
+    }
+    normal
+}
+
+fn is_index(arg: &str) -> bool {
+    (arg.starts_with('+') || arg.starts_with('-')) &&
+        arg.len() > 1 && arg[1..].bytes().all(|b| b.is_ascii_digit())
+}
+
+// The position in a stack of `len` directories of `+n` from the left, or
+// `-n` from the right.
+fn index(arg: &str, len: usize) -> std::result::Result<usize, String> {
+    let n: usize = arg[1..].parse().unwrap_or(std::usize::MAX);
+    match (arg.starts_with('+'), n < len) {
+        (true, true) => Ok(n),
+        (false, true) => Ok(len - 1 - n),
+        _ => Err(format!("{}: directory stack index out of range", arg)),
+    }
+}
+
+// Parse the `-L` and `-P` options, the last of which wins, returning true
+// for physical mode and the remaining operands.
+fn options(args: &[String]) -> std::result::Result<(bool, &[String]), String> {
+    let mut physical = false;
+    for (i, arg) in args.iter().enumerate() {
+        if arg == "--" {
+            return Ok((physical, &args[i + 1..]));
+        } else if arg == "-" || !arg.starts_with('-') {
+            return Ok((physical, &args[i..]));
+        }
+        for flag in arg[1..].chars() {
+            match flag {
+                'L' => physical = false,
+                'P' => physical = true,
+                c => return Err(format!("-{}: invalid option", c)),
+            }
+        }
+    }
+    Ok((physical, &[]))
+}
+
+fn strings(argv: &[CString]) -> Vec<String> {
+    argv.iter().skip(1).map(|a| a.to_string_lossy().into_owned()).collect()
+}
+
+fn failure(io: IO, name: &str, message: &str, code: i32) -> WaitStatus {
+    writeln!(io.stderr(), "oursh: {}: {}", name, message).ok();
+    WaitStatus::Exited(Pid::this(), code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_path() {
+        assert_eq!(PathBuf::from("/a/c"), normalize(Path::new("/a/./b/../c")));
+        assert_eq!(PathBuf::from("/"), normalize(Path::new("/../..")));
+        assert_eq!(PathBuf::from("/a"), normalize(Path::new("/a/b/..")));
+    }
+
+    #[test]
+    fn stack_index() {
+        assert!(is_index("+0") && is_index("-12"));
+        assert!(!is_index("+") && !is_index("dir") && !is_index("-x"));
+        assert_eq!(Ok(1), index("+1", 3));
+        assert_eq!(Ok(2), index("-0", 3));
+        assert!(index("+3", 3).is_err());
+    }
+}
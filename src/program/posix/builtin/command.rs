@@ -0,0 +1,274 @@
+//! The `command`, `type` and `hash` builtins, which find commands.
+//!
+//! A command name is found as the first of an alias, a reserved word, a
+//! special builtin, a function, a builtin or an executable in the `$PATH`,
+//! see `lookup`.
+use std::{
+    env,
+    ffi::{CString, OsStr},
+    io::Write,
+    os::unix::ffi::OsStrExt,
+    path::PathBuf,
+};
+use nix::{
+    unistd::Pid,
+    sys::wait::WaitStatus,
+};
+use crate::{
+    lookup,
+    program::{Result, Error, IO, posix::{alias, lex}, runtime::Runtime},
+    job::{Job, Jobs as JobsRef},
+};
+use super::{Builtin, output, quote};
+
+/// Command running and finding (`command`) builtin.
+///
+/// ```sh
+/// command ls -l
+/// command -v ls
+/// command -pV ls
+/// ```
+///
+/// The command is run without any alias substitution or functions, as a
+/// builtin or an executable. Errors of special builtins run this way aren't
+/// fatal. With `-v` the alias, builtin or path of the command is
+/// printed instead, or with `-V` a description like `type`'s. The `-p`
+/// option searches the default `$PATH` for the standard utilities.
+pub struct Command;
+
+impl Builtin for Command {
+    fn run(argv: Vec<CString>, io: IO, jobs: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let (mut default, mut verbose, mut describe) = (false, false, false);
+        let mut i = 1;
+        while let Some(arg) = argv.get(i).map(|a| a.to_string_lossy()) {
+            if arg == "--" {
+                i += 1;
+                break;
+            } else if !arg.starts_with('-') || arg == "-" {
+                break;
+            }
+            for flag in arg[1..].chars() {
+                match flag {
+                    'p' => default = true,
+                    'v' => describe = true,
+                    'V' => verbose = true,
+                    c => {
+                        writeln!(io.stderr(), "oursh: command: -{}: invalid option",
+                                 c).ok();
+                        return Ok(WaitStatus::Exited(Pid::this(), 2));
+                    },
+                }
+            }
+            i += 1;
+        }
+        let path = Some(OsStr::new(lookup::DEFAULT_PATH)).filter(|_| default);
+
+        if describe || verbose {
+            let names: Vec<_> = argv[i..].iter().map(|a| {
+                a.to_string_lossy()
+            }).collect();
+            if names.is_empty() {
+                writeln!(io.stderr(), "oursh: command: name expected").ok();
+                return Ok(WaitStatus::Exited(Pid::this(), 2));
+            }
+
+            let mut text = String::new();
+            let mut code = 0;
+            for name in names {
+                match (Kind::of(&name, path, &runtime), verbose) {
+                    (Some(kind), true) => {
+                        text += &format!("{}\n", kind.describe(&name));
+                    },
+                    (Some(Kind::Alias(value)), false) => {
+                        text += &format!("alias {}={}\n", name, quote(value));
+                    },
+                    (Some(Kind::Hashed(file)), false) |
+                    (Some(Kind::File(file)), false) => {
+                        text += &format!("{}\n", file.display());
+                    },
+                    (Some(_), false) => text += &format!("{}\n", name),
+                    (None, _) => {
+                        if verbose {
+                            writeln!(io.stderr(), "oursh: command: {}: not found",
+                                     name).ok();
+                        }
+                        code = 1;
+                    },
+                }
+            }
+            return Ok(status(output("command", io, text.as_bytes()), code));
+        }
+
+        let name = match argv.get(i) {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => return Ok(WaitStatus::Exited(Pid::this(), 0)),
+        };
+
+        let mut argv = argv[i..].to_vec();
+        if let Some(builtin) = super::get(&name) {
+            // Errors of special builtins don't exit the shell when they're
+            // run by `command`, they only fail like any other command.
+            return match builtin(argv, io, jobs, runtime) {
+                Err(error @ Error::Builtin { .. }) |
+                Err(error @ Error::Redirect { .. }) |
+                Err(error @ Error::Parse { .. }) => {
+                    writeln!(io.stderr(), "oursh: {}", error).ok();
+                    Ok(WaitStatus::Exited(Pid::this(), error.status()))
+                },
+                status => status,
+            };
+        }
+        if let Some(path) = path {
+            match lookup::search(&name, path) {
+                Some(file) => {
+                    argv[0] = CString::new(file.as_os_str().as_bytes())
+                        .expect("error in path");
+                },
+                None => {
+                    writeln!(io.stderr(), "oursh: command: {}: not found", name).ok();
+                    return Ok(WaitStatus::Exited(Pid::this(), 127));
+                },
+            }
+        }
+        Ok(Job::new(argv).fork_and_wait(io)?)
+    }
+}
+
+/// Command description (`type`) builtin.
+///
+/// ```sh
+/// type cd ls
+/// ```
+///
+/// Prints whether each name is an alias, a reserved word, a function, a
+/// builtin or an executable, and where the executable is.
+pub struct Type;
+
+impl Builtin for Type {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let mut text = String::new();
+        let mut code = 0;
+        for name in argv.iter().skip(1).map(|a| a.to_string_lossy()) {
+            match Kind::of(&name, None, &runtime) {
+                Some(kind) => text += &format!("{}\n", kind.describe(&name)),
+                None => {
+                    writeln!(io.stderr(), "oursh: type: {}: not found", name).ok();
+                    code = 1;
+                },
+            }
+        }
+        Ok(status(output("type", io, text.as_bytes()), code))
+    }
+}
+
+/// Remembered command locations (`hash`) builtin.
+///
+/// ```sh
+/// hash
+/// hash ls cat
+/// hash -r
+/// ```
+///
+/// Without arguments the location of every remembered command is printed.
+/// Each name given is found and remembered, and `-r` forgets every
+/// location.
+pub struct Hash;
+
+impl Builtin for Hash {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let args: Vec<String> = argv.iter().skip(1).map(|a| {
+            a.to_string_lossy().into_owned()
+        }).collect();
+
+        match args.first().map(|a| a.as_str()) {
+            None => {
+                let text: String = lookup::remembered().iter().map(|(_, path)| {
+                    format!("{}\n", path.display())
+                }).collect();
+                return Ok(output("hash", io, text.as_bytes()));
+            },
+            Some("-r") => {
+                lookup::forget();
+                return Ok(WaitStatus::Exited(Pid::this(), 0));
+            },
+            _ => {},
+        }
+
+        let mut code = 0;
+        for name in args.iter().skip_while(|a| *a == "--") {
+            // Builtins and functions are never searched for, like other
+            // shells.
+            let function = runtime.borrow().functions.contains_key(name);
+            if !function && super::get(name).is_none() && lookup::find(name).is_none() {
+                writeln!(io.stderr(), "oursh: hash: {}: not found", name).ok();
+                code = 1;
+            }
+        }
+        Ok(WaitStatus::Exited(Pid::this(), code))
+    }
+}
+
+// The status of a builtin which wrote its output, failing with `code` when
+// that succeeded.
+fn status(written: WaitStatus, code: i32) -> WaitStatus {
+    match written {
+        WaitStatus::Exited(pid, 0) => WaitStatus::Exited(pid, code),
+        status => status,
+    }
+}
+
+// What a command name is found to be.
+enum Kind {
+    Alias(&'static str),
+    Reserved,
+    Special,
+    Function,
+    Builtin,
+    Hashed(PathBuf),
+    File(PathBuf),
+}
+
+impl Kind {
+    // Find what the command name is, searching the given `$PATH` or the
+    // shell's own, without remembering where executables are.
+    fn of(name: &str, path: Option<&OsStr>, runtime: &Runtime) -> Option<Kind> {
+        if let Some(value) = alias::get(name) {
+            Some(Kind::Alias(value))
+        } else if lex::is_reserved(name) {
+            Some(Kind::Reserved)
+        } else if super::is_special(name) && super::get(name).is_some() {
+            Some(Kind::Special)
+        } else if runtime.borrow().functions.contains_key(name) {
+            Some(Kind::Function)
+        } else if super::get(name).is_some() {
+            Some(Kind::Builtin)
+        } else if name.contains('/') {
+            lookup::find(name).map(Kind::File)
+        } else if let Some(path) = path {
+            lookup::search(name, path).map(Kind::File)
+        } else if let Some(file) = lookup::hashed(name) {
+            Some(Kind::Hashed(file))
+        } else {
+            let path = env::var_os("PATH")?;
+            lookup::search(name, &path).map(Kind::File)
+        }
+    }
+
+    fn describe(&self, name: &str) -> String {
+        match self {
+            Kind::Alias(value) => format!("{} is an alias for {}", name, value),
+            Kind::Reserved => format!("{} is a reserved word", name),
+            Kind::Special => format!("{} is a special shell builtin", name),
+            Kind::Function => format!("{} is a function", name),
+            Kind::Builtin => format!("{} is a shell builtin", name),
+            Kind::Hashed(path) => format!("{} is hashed ({})", name, path.display()),
+            Kind::File(path) => format!("{} is {}", name, path.display()),
+        }
+    }
+}
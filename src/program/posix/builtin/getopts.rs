@@ -0,0 +1,225 @@
+//! The `getopts` builtin, which parses the options of scripts.
+//!
+//! Each call finds the next option, keeping the index of the next argument
+//! in `$OPTIND`. Within a group of flags like `-abc` the position of the
+//! next flag is kept by the shell, and forgotten whenever `$OPTIND` is set
+//! to something else, for example `OPTIND=1` to start over.
+use std::{ffi::CString, io::Write};
+use nix::{
+    unistd::Pid,
+    sys::wait::WaitStatus,
+};
+use crate::{
+    program::{Result, IO, posix::ast, runtime::Runtime},
+    job::Jobs as JobsRef,
+};
+use super::Builtin;
+
+/// Option parsing (`getopts`) builtin.
+///
+/// ```sh
+/// while getopts ab:c name; do
+///     if test "$name" = '?'; then
+///         exit 2
+///     fi
+///     echo "-$name $OPTARG"
+/// done
+/// shift $(expr $OPTIND - 1)
+/// ```
+///
+/// Each letter of the option string is an option, which takes an argument
+/// in `$OPTARG` when followed by a `:`. The options of the positional
+/// parameters are parsed, unless other arguments are given.
+///
+/// Unknown options and missing arguments set the name to `?` and print an
+/// error, unless the option string starts with a `:`. Then nothing is
+/// printed, the option is set in `$OPTARG` and the name is set to `:` for a
+/// missing argument.
+pub struct Getopts;
+
+impl Builtin for Getopts {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let args: Vec<String> = argv.iter().skip(1).map(|a| {
+            a.to_string_lossy().into_owned()
+        }).collect();
+        if args.len() < 2 {
+            writeln!(io.stderr(),
+                     "oursh: getopts: usage: getopts optstring name [arg ...]").ok();
+            return Ok(WaitStatus::Exited(Pid::this(), 2));
+        } else if !ast::is_name(&args[1]) {
+            writeln!(io.stderr(), "oursh: getopts: {}: invalid variable name",
+                     args[1]).ok();
+            return Ok(WaitStatus::Exited(Pid::this(), 2));
+        }
+
+        let mut state = runtime.borrow_mut();
+        let operands = if args.len() > 2 {
+            args[2..].to_vec()
+        } else {
+            state.args.clone()
+        };
+        let optind = state.get("OPTIND")
+                          .and_then(|i| i.parse().ok())
+                          .filter(|i| *i > 0)
+                          .unwrap_or(1);
+        let position = match state.getopts {
+            (i, position) if i == optind => position,
+            _ => 1,
+        };
+
+        let step = next(&args[0], &operands, optind, position);
+        if let Some(error) = &step.error {
+            writeln!(io.stderr(), "{}: {}", state.name, error).ok();
+        }
+        state.set(&args[1], &step.name.to_string());
+        match step.optarg {
+            Some(optarg) => state.set("OPTARG", &optarg),
+            None => state.unset("OPTARG"),
+        }
+        state.set("OPTIND", &step.optind.to_string());
+        state.getopts = (step.optind, step.position);
+
+        let code = if step.done { 1 } else { 0 };
+        Ok(WaitStatus::Exited(Pid::this(), code))
+    }
+}
+
+// The result of parsing one option.
+#[derive(Debug, PartialEq)]
+struct Step {
+    name: char,
+    optarg: Option<String>,
+    optind: usize,
+    position: usize,
+    error: Option<String>,
+    done: bool,
+}
+
+// Parse the option at `position` in the argument at `optind`, which both
+// count from 1.
+fn next(optstring: &str, args: &[String], optind: usize, position: usize)
+    -> Step
+{
+    let silent = optstring.starts_with(':');
+    let mut step = Step {
+        name: '?',
+        optarg: None,
+        optind,
+        position: 1,
+        error: None,
+        done: false,
+    };
+
+    let arg: Vec<char> = match args.get(optind - 1) {
+        Some(arg) => arg.chars().collect(),
+        None => {
+            step.done = true;
+            return step;
+        },
+    };
+    if position == 1 {
+        if arg == ['-', '-'] {
+            step.optind += 1;
+            step.done = true;
+            return step;
+        } else if arg.len() < 2 || arg[0] != '-' {
+            step.done = true;
+            return step;
+        }
+    }
+
+    let position = position.min(arg.len() - 1);
+    let option = arg[position];
+    let rest: String = arg[position + 1..].iter().collect();
+    if rest.is_empty() {
+        step.optind += 1;
+    } else {
+        step.position = position + 1;
+    }
+
+    let spec = optstring.char_indices().find(|&(_, c)| c == option && c != ':');
+    match spec {
+        None => {
+            if silent {
+                step.optarg = Some(option.to_string());
+            } else {
+                step.error = Some(format!("illegal option -- {}", option));
+            }
+        },
+        Some((i, _)) if optstring[i + 1..].starts_with(':') => {
+            step.position = 1;
+            if !rest.is_empty() {
+                step.name = option;
+                step.optarg = Some(rest);
+                step.optind += 1;
+            } else if let Some(optarg) = args.get(step.optind - 1) {
+                step.name = option;
+                step.optarg = Some(optarg.clone());
+                step.optind += 1;
+            } else if silent {
+                step.name = ':';
+                step.optarg = Some(option.to_string());
+            } else {
+                step.error = Some(format!("option requires an argument -- {}",
+                                          option));
+            }
+        },
+        Some(_) => step.name = option,
+    }
+    step
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parse every option, returning each name and `$OPTARG`, and the final
+    // `$OPTIND`.
+    fn parse(optstring: &str, args: &[&str])
+        -> (Vec<(char, Option<String>)>, usize)
+    {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let (mut optind, mut position) = (1, 1);
+        let mut options = vec![];
+        loop {
+            let step = next(optstring, &args, optind, position);
+            optind = step.optind;
+            position = step.position;
+            if step.done {
+                return (options, optind);
+            }
+            options.push((step.name, step.optarg));
+        }
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!((vec![('a', None), ('b', None), ('c', None)], 3),
+                   parse("abc", &["-ab", "-c", "x"]));
+        assert_eq!((vec![('a', None)], 3), parse("a", &["-a", "--", "-a"]));
+        assert_eq!((vec![], 1), parse("a", &["-", "-a"]));
+        assert_eq!((vec![], 1), parse("a", &[]));
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!((vec![('a', Some("x".into())), ('b', None),
+                         ('a', Some("y".into()))], 5),
+                   parse("a:b", &["-ax", "-b", "-a", "y"]));
+        assert_eq!((vec![('b', None), ('a', Some("-b".into()))], 3),
+                   parse("a:b", &["-ba", "-b"]));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!((vec![('?', None), ('?', None)], 3),
+                   parse("a:", &["-x", "-a"]));
+        assert_eq!((vec![('?', Some("x".into())), (':', Some("a".into()))], 3),
+                   parse(":a:", &["-x", "-a"]));
+        let args = vec!["-x".to_string()];
+        assert!(next("a", &args, 1, 1).error.is_some());
+        assert!(next(":a", &args, 1, 1).error.is_none());
+    }
+}
@@ -0,0 +1,121 @@
+//! The `json` builtin, which binds a JSON value to variables.
+use std::{ffi::CString, io::Write};
+use nix::{
+    unistd::Pid,
+    sys::wait::WaitStatus,
+};
+use serde_json::Value;
+use crate::{
+    program::{Result, IO, posix::ast, runtime::Runtime},
+    job::Jobs as JobsRef,
+};
+use super::Builtin;
+
+/// JSON binding builtin.
+///
+/// ```sh
+/// json "$({#!python; print('{"year": 2020, "tags": ["a"]}')})"
+/// echo $year $tags
+/// json -n date "$({#!ruby; require 'json'; puts Time.now.to_s.to_json})"
+/// ```
+///
+/// The arguments are the text of a command's output, whose last line is
+/// its final JSON value, or when that isn't valid JSON, the whole text is.
+/// Each member of an object is assigned to the variable of the same name,
+/// with `-n name` the whole value is assigned to `name`.
+///
+/// Strings are assigned without quotes, `null` is the empty string, and
+/// arrays or objects are assigned as JSON.
+pub struct Json;
+
+impl Builtin for Json {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let mut args: Vec<String> = argv.iter().skip(1).map(|a| {
+            a.to_string_lossy().into_owned()
+        }).collect();
+
+        let name = match args.first().map(String::as_str) {
+            Some("-n") if args.len() > 1 => {
+                let name = args.remove(1);
+                args.remove(0);
+                if !ast::is_name(&name) {
+                    return failure(io, &format!("{}: invalid variable name", name), 2);
+                }
+                Some(name)
+            },
+            Some("-n") => return failure(io, "-n: name expected", 2),
+            Some("--") => {
+                args.remove(0);
+                None
+            },
+            _ => None,
+        };
+
+        let value = match parse(&args.join(" ")) {
+            Some(value) => value,
+            None => return failure(io, "invalid JSON", 1),
+        };
+
+        let mut state = runtime.borrow_mut();
+        match (name, value) {
+            (Some(name), value) => state.set(&name, &string(&value)),
+            (None, Value::Object(members)) => {
+                let mut code = 0;
+                for (name, value) in members {
+                    if ast::is_name(&name) {
+                        state.set(&name, &string(&value));
+                    } else {
+                        writeln!(io.stderr(), "oursh: json: {}: invalid variable name",
+                                 name).ok();
+                        code = 1;
+                    }
+                }
+                return Ok(WaitStatus::Exited(Pid::this(), code));
+            },
+            (None, _) => return failure(io, "object expected, or -n name", 1),
+        }
+        Ok(WaitStatus::Exited(Pid::this(), 0))
+    }
+}
+
+// The final JSON value of some text.
+fn parse(text: &str) -> Option<Value> {
+    let last = text.lines().rev().find(|line| !line.trim().is_empty())?;
+    serde_json::from_str(last).or_else(|_| serde_json::from_str(text)).ok()
+}
+
+// The value of a variable bound to a JSON value.
+fn string(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(string) => string.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn failure(io: IO, message: &str, code: i32) -> Result<WaitStatus> {
+    writeln!(io.stderr(), "oursh: json: {}", message).ok();
+    Ok(WaitStatus::Exited(Pid::this(), code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn final_value() {
+        assert_eq!(Some(Value::from(2)), parse("1\n2\n\n"));
+        assert_eq!(Some(Value::from(vec![1, 2])), parse("[1,\n 2]"));
+        assert_eq!(None, parse("not json"));
+        assert_eq!(None, parse(""));
+    }
+
+    #[test]
+    fn strings() {
+        let value: Value = serde_json::from_str(r#"[null, "a b", 1.5, true, {"a": [1]}]"#).unwrap();
+        let strings: Vec<_> = value.as_array().unwrap().iter().map(string).collect();
+        assert_eq!(vec!["", "a b", "1.5", "true", r#"{"a":[1]}"#], strings);
+    }
+}
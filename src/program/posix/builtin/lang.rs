@@ -0,0 +1,122 @@
+//! The `lang` builtin, which registers the interpreters of `{#lang ...}`
+//! blocks, see `hashlang`.
+use std::{ffi::CString, io::Write};
+use nix::{
+    unistd::Pid,
+    sys::wait::WaitStatus,
+};
+use crate::{
+    program::{Result, IO, posix::hashlang::{self, Language}, runtime::Runtime},
+    job::Jobs as JobsRef,
+};
+use super::{Builtin, output, quote};
+
+/// Language registration (`lang`) builtin.
+///
+/// ```sh
+/// lang awk awk -f
+/// lang -e ts ts ts-node '{}'
+/// lang -i sql sqlite3 db.sqlite
+/// lang awk
+/// lang -u sql
+/// ```
+///
+/// Each language is given the command line of its interpreter, where a `{}`
+/// word is replaced by the file of the block's text, or which is given the
+/// file last. With `-e` the file has the given extension, and with `-i` the
+/// text is given on the interpreter's standard input instead.
+///
+/// With only names each language is printed so it can be read again by the
+/// shell, or every language without any arguments. With `-u` each language
+/// is removed.
+pub struct Lang;
+
+impl Builtin for Lang {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, _: Runtime)
+        -> Result<WaitStatus>
+    {
+        let args: Vec<String> = argv.iter().skip(1).map(|a| {
+            a.to_string_lossy().into_owned()
+        }).collect();
+
+        let (mut extension, mut stdin, mut remove) = (None, false, false);
+        let mut i = 0;
+        while let Some(arg) = args.get(i) {
+            i += 1;
+            match arg.as_str() {
+                "--" => break,
+                "-e" => match args.get(i) {
+                    Some(e) => {
+                        extension = Some(e.trim_start_matches('.').to_string());
+                        i += 1;
+                    },
+                    None => return failure(io, "-e: extension expected", 2),
+                },
+                "-i" => stdin = true,
+                "-u" => remove = true,
+                arg if arg.starts_with('-') && arg != "-" => {
+                    return failure(io, &format!("{}: invalid option", arg), 2);
+                },
+                _ => {
+                    i -= 1;
+                    break;
+                },
+            }
+        }
+        let args = &args[i..];
+
+        if remove {
+            let mut code = 0;
+            for name in args {
+                if !hashlang::remove(name) {
+                    writeln!(io.stderr(), "oursh: lang: {}: not found", name).ok();
+                    code = 1;
+                }
+            }
+            return Ok(WaitStatus::Exited(Pid::this(), code));
+        }
+
+        match args.len() {
+            0 => {
+                let text: String = hashlang::all().iter().map(|(name, language)| {
+                    print(name, language)
+                }).collect();
+                Ok(output("lang", io, text.as_bytes()))
+            },
+            1 => match hashlang::get(&args[0]) {
+                Some(language) => {
+                    Ok(output("lang", io, print(&args[0], &language).as_bytes()))
+                },
+                None => failure(io, &format!("{}: not found", args[0]), 1),
+            },
+            _ => {
+                hashlang::set(&args[0], Language {
+                    argv: args[1..].to_vec(),
+                    extension,
+                    stdin,
+                });
+                Ok(WaitStatus::Exited(Pid::this(), 0))
+            },
+        }
+    }
+}
+
+// The command which registers the language again.
+fn print(name: &str, language: &Language) -> String {
+    let mut words = vec!["lang".to_string()];
+    if let Some(extension) = &language.extension {
+        words.push("-e".into());
+        words.push(quote(extension));
+    }
+    if language.stdin {
+        words.push("-i".into());
+    }
+    words.push(quote(name));
+    words.extend(language.argv.iter().map(|w| quote(w)));
+    format!("{}\n", words.join(" "))
+}
+
+fn failure(io: IO, message: &str, code: i32) -> Result<WaitStatus> {
+    writeln!(io.stderr(), "oursh: lang: {}", message).ok();
+    Ok(WaitStatus::Exited(Pid::this(), code))
+}
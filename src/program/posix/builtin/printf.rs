@@ -0,0 +1,579 @@
+//! The `printf` builtin, and the backslash escapes it shares with `echo`.
+//!
+//! Formats follow POSIX, with the conversions `d i o u x X f F e E g G c s
+//! b` and `%%`. As an extension, `%q` quotes its argument so the shell reads
+//! it back as a single word. The format is reused until every argument has
+//! been consumed.
+use std::{
+    ffi::CString,
+    io::Write,
+    slice,
+};
+use nix::{
+    unistd::Pid,
+    sys::wait::WaitStatus,
+};
+use crate::{
+    program::{Result, IO, runtime::Runtime},
+    job::Jobs as JobsRef,
+};
+use super::{Builtin, quote};
+
+/// Formatted printing builtin.
+///
+/// ```sh
+/// printf '%s=%d\n' a 1 b 2
+/// printf '%-10s|%5.2f\n' name 3.14159
+/// ```
+pub struct Printf;
+
+impl Builtin for Printf {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, _: Runtime)
+        -> Result<WaitStatus>
+    {
+        let args: Vec<String> = argv.iter().skip(1).map(|a| {
+            a.to_string_lossy().into_owned()
+        }).collect();
+
+        let (text, args) = match args.split_first() {
+            Some(split) => split,
+            None => {
+                writeln!(io.stderr(),
+                         "oursh: printf: usage: printf format [arguments]").ok();
+                return Ok(WaitStatus::Exited(Pid::this(), 2));
+            },
+        };
+
+        let (output, errors) = format(text, args);
+        for error in &errors {
+            writeln!(io.stderr(), "oursh: printf: {}", error).ok();
+        }
+        let mut code = !errors.is_empty() as i32;
+        if let Err(e) = io.stdout().write_all(&output) {
+            writeln!(io.stderr(), "oursh: printf: write error: {}", e).ok();
+            code = 1;
+        }
+        Ok(WaitStatus::Exited(Pid::this(), code))
+    }
+}
+
+/// Format the arguments, returning the output and an error for each
+/// invalid argument or conversion.
+///
+/// ```
+/// use oursh::program::posix::builtin::printf::format;
+///
+/// let (output, errors) = format("%s=%03d\\n", &["a".into(), "1".into(),
+///                                              "b".into(), "x".into()]);
+/// assert_eq!(b"a=001\nb=000\n", &output[..]);
+/// assert_eq!(vec!["x: invalid number"], errors);
+/// ```
+pub fn format(format: &str, args: &[String]) -> (Vec<u8>, Vec<String>) {
+    let mut printer = Printer {
+        args: args.iter(),
+        consumed: 0,
+        output: vec![],
+        errors: vec![],
+    };
+    loop {
+        let consumed = printer.consumed;
+        if !printer.format(format) {
+            break;
+        }
+        // Only reuse the format while it's still using up arguments.
+        if printer.args.len() == 0 || printer.consumed == consumed {
+            break;
+        }
+    }
+    (printer.output, printer.errors)
+}
+
+/// Expand the backslash escapes of `text` into `output`, returning false
+/// if a `\c` asked for all output to stop.
+///
+/// In `echo` (and `printf %b`) octal escapes are written `\0ddd`, while in
+/// `printf` formats they're written `\ddd`.
+///
+/// ```
+/// use oursh::program::posix::builtin::printf::escape;
+///
+/// let mut output = vec![];
+/// assert!(escape(r"a\tb\0101", true, &mut output));
+/// assert_eq!(b"a\tbA", &output[..]);
+///
+/// let mut output = vec![];
+/// assert!(!escape(r"a\cb", true, &mut output));
+/// assert_eq!(b"a", &output[..]);
+/// ```
+pub fn escape(text: &str, echo: bool, output: &mut Vec<u8>) -> bool {
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            output.extend(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+
+        match chars.peek().cloned() {
+            Some('a')  => output.push(0x07),
+            Some('b')  => output.push(0x08),
+            Some('f')  => output.push(0x0c),
+            Some('n')  => output.push(b'\n'),
+            Some('r')  => output.push(b'\r'),
+            Some('t')  => output.push(b'\t'),
+            Some('v')  => output.push(0x0b),
+            Some('\\') => output.push(b'\\'),
+            Some('c')  => return false,
+            Some(d) if d.is_digit(8) && (!echo || d == '0') => {
+                if echo {
+                    chars.next();
+                }
+                let mut n: u32 = 0;
+                for _ in 0..3 {
+                    match chars.peek().and_then(|d| d.to_digit(8)) {
+                        Some(d) => {
+                            n = n * 8 + d;
+                            chars.next();
+                        },
+                        None => break,
+                    }
+                }
+                output.push(n as u8);
+                continue;
+            },
+            _ => {
+                output.push(b'\\');
+                continue;
+            },
+        }
+        chars.next();
+    }
+    true
+}
+
+// The state of a single run of `printf`.
+struct Printer<'a> {
+    args: slice::Iter<'a, String>,
+    consumed: usize,
+    output: Vec<u8>,
+    errors: Vec<String>,
+}
+
+// A conversion specification, like the `-08.3` of `%-08.3f`.
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl<'a> Printer<'a> {
+    // Write the format once, returning false if output should stop.
+    fn format(&mut self, format: &str) -> bool {
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    // Escapes are expanded one at a time, so `\c` can stop.
+                    let mut escaped = String::from("\\");
+                    match chars.peek().cloned() {
+                        Some(d) if d.is_digit(8) => {
+                            while escaped.len() < 4 &&
+                                  chars.peek().map_or(false, |d| d.is_digit(8))
+                            {
+                                escaped.push(chars.next().unwrap());
+                            }
+                        },
+                        Some(d) => {
+                            escaped.push(d);
+                            chars.next();
+                        },
+                        None => {},
+                    }
+                    if !escape(&escaped, false, &mut self.output) {
+                        return false;
+                    }
+                },
+                '%' if chars.peek() == Some(&'%') => {
+                    chars.next();
+                    self.output.push(b'%');
+                },
+                '%' => {
+                    let mut spec = Spec::default();
+                    while let Some(&flag) = chars.peek() {
+                        match flag {
+                            '-' => spec.left = true,
+                            '+' => spec.plus = true,
+                            ' ' => spec.space = true,
+                            '#' => spec.alternate = true,
+                            '0' => spec.zero = true,
+                            _ => break,
+                        }
+                        chars.next();
+                    }
+
+                    if chars.peek() == Some(&'*') {
+                        chars.next();
+                        let width = self.integer();
+                        spec.left |= width < 0;
+                        spec.width = width.abs() as usize;
+                    } else {
+                        spec.width = number(&mut chars);
+                    }
+                    if chars.peek() == Some(&'.') {
+                        chars.next();
+                        if chars.peek() == Some(&'*') {
+                            chars.next();
+                            let precision = self.integer();
+                            if precision >= 0 {
+                                spec.precision = Some(precision as usize);
+                            }
+                        } else {
+                            spec.precision = Some(number(&mut chars));
+                        }
+                    }
+
+                    match chars.next() {
+                        Some(conversion) => {
+                            if !self.convert(conversion, &spec) {
+                                return false;
+                            }
+                        },
+                        None => {
+                            self.errors.push("%: missing conversion".into());
+                            return false;
+                        },
+                    }
+                },
+                c => {
+                    let mut buffer = [0; 4];
+                    self.output.extend(c.encode_utf8(&mut buffer).as_bytes());
+                },
+            }
+        }
+        true
+    }
+
+    // Write a single conversion, returning false if output should stop.
+    fn convert(&mut self, conversion: char, spec: &Spec) -> bool {
+        let text = match conversion {
+            'd' | 'i' => {
+                let n = self.integer();
+                let digits = digits((n as i128).abs().to_string(), spec);
+                return self.number(sign(n < 0, spec), &digits, spec);
+            },
+            'u' => {
+                let digits = digits((self.integer() as u64).to_string(), spec);
+                return self.number("", &digits, spec);
+            },
+            'o' => {
+                let mut digits = digits(format!("{:o}", self.integer()), spec);
+                if spec.alternate && !digits.starts_with('0') {
+                    digits.insert(0, '0');
+                }
+                return self.number("", &digits, spec);
+            },
+            'x' | 'X' => {
+                let n = self.integer();
+                let mut digits = digits(format!("{:x}", n), spec);
+                let mut prefix = if spec.alternate && n != 0 { "0x" } else { "" };
+                if conversion == 'X' {
+                    digits = digits.to_uppercase();
+                    prefix = if prefix.is_empty() { "" } else { "0X" };
+                }
+                return self.number(prefix, &digits, spec);
+            },
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let n = self.float();
+                let digits = float(n.abs(), conversion, spec);
+                // Infinities and NaN are never padded with zeros.
+                let spec = Spec {
+                    zero: spec.zero && n.is_finite(),
+                    ..*spec
+                };
+                let negative = n.is_sign_negative() && !n.is_nan();
+                return self.number(sign(negative, &spec), &digits, &spec);
+            },
+            'c' => {
+                self.string().chars().next().map(String::from).unwrap_or_default()
+            },
+            's' => truncate(self.string(), spec),
+            'q' => quote(self.string()),
+            'b' => {
+                // The escapes may write any bytes, so they're kept as they
+                // are, with the precision counting bytes.
+                let mut output = vec![];
+                let go = escape(self.string(), true, &mut output);
+                if let Some(precision) = spec.precision {
+                    output.truncate(precision);
+                }
+                self.pad(&output, spec);
+                return go;
+            },
+            c => {
+                self.errors.push(format!("%{}: invalid conversion", c));
+                return false;
+            },
+        };
+        self.pad(text.as_bytes(), spec);
+        true
+    }
+
+    // Write a number, padding it with spaces or zeros.
+    fn number(&mut self, prefix: &str, digits: &str, spec: &Spec) -> bool {
+        let length = prefix.chars().count() + digits.chars().count();
+        // Zeros pad between the sign and the digits, unless the precision
+        // of an integer already sets the number of digits.
+        if spec.zero && !spec.left && length < spec.width {
+            let zeros = "0".repeat(spec.width - length);
+            self.output.extend(format!("{}{}{}", prefix, zeros, digits).bytes());
+        } else {
+            self.pad(format!("{}{}", prefix, digits).as_bytes(), spec);
+        }
+        true
+    }
+
+    // Write text, padded with spaces to the width.
+    fn pad(&mut self, text: &[u8], spec: &Spec) {
+        let length = match std::str::from_utf8(text) {
+            Ok(text) => text.chars().count(),
+            Err(_) => text.len(),
+        };
+        let padding = " ".repeat(spec.width.saturating_sub(length));
+        if spec.left {
+            self.output.extend(text);
+            self.output.extend(padding.bytes());
+        } else {
+            self.output.extend(padding.bytes());
+            self.output.extend(text);
+        }
+    }
+
+    // The next argument, or an empty string once they've run out.
+    fn string(&mut self) -> &'a str {
+        match self.args.next() {
+            Some(arg) => {
+                self.consumed += 1;
+                arg
+            },
+            None => "",
+        }
+    }
+
+    // The next argument as an integer, like `42`, `-0x2a`, `052` or `'*`.
+    fn integer(&mut self) -> i64 {
+        let arg = self.string();
+        let text = arg.trim_start();
+        if let Some(c) = character(text) {
+            return c as i64;
+        }
+
+        let (negative, digits) = match text.chars().next() {
+            Some('-') => (true, &text[1..]),
+            Some('+') => (false, &text[1..]),
+            _ => (false, text),
+        };
+        let (radix, digits) = if digits.starts_with("0x") ||
+                                 digits.starts_with("0X") {
+            (16, &digits[2..])
+        } else if digits.starts_with('0') && digits.len() > 1 {
+            (8, &digits[1..])
+        } else {
+            (10, digits)
+        };
+
+        if text.is_empty() {
+            0
+        } else if let Ok(n) = i64::from_str_radix(digits, radix) {
+            if negative { -n } else { n }
+        } else {
+            self.errors.push(format!("{}: invalid number", arg));
+            0
+        }
+    }
+
+    // The next argument as a floating point number.
+    fn float(&mut self) -> f64 {
+        let arg = self.string();
+        let text = arg.trim();
+        if let Some(c) = character(text) {
+            return c as u32 as f64;
+        }
+
+        if text.is_empty() {
+            0.0
+        } else if let Ok(n) = text.parse() {
+            n
+        } else {
+            self.errors.push(format!("{}: invalid number", arg));
+            0.0
+        }
+    }
+}
+
+// The character of a numeric argument starting with a quote, as in `'a`.
+fn character(text: &str) -> Option<char> {
+    if text.starts_with('\'') || text.starts_with('"') {
+        Some(text[1..].chars().next().unwrap_or('\0'))
+    } else {
+        None
+    }
+}
+
+// Read a decimal number from a format, or 0 if there isn't one.
+fn number<I>(chars: &mut std::iter::Peekable<I>) -> usize
+    where I: Iterator<Item = char>
+{
+    let mut n = 0;
+    while let Some(d) = chars.peek().and_then(|d| d.to_digit(10)) {
+        n = n * 10 + d as usize;
+        chars.next();
+    }
+    n
+}
+
+fn sign(negative: bool, spec: &Spec) -> &'static str {
+    if negative {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else if spec.space {
+        " "
+    } else {
+        ""
+    }
+}
+
+// Pad the digits of an integer with zeros to the precision, where a zero
+// with a precision of zero has no digits at all.
+fn digits(digits: String, spec: &Spec) -> String {
+    match spec.precision {
+        Some(0) if digits == "0" => String::new(),
+        Some(precision) if digits.len() < precision => {
+            format!("{}{}", "0".repeat(precision - digits.len()), digits)
+        },
+        _ => digits,
+    }
+}
+
+fn truncate(text: &str, spec: &Spec) -> String {
+    match spec.precision {
+        Some(precision) => text.chars().take(precision).collect(),
+        None => text.into(),
+    }
+}
+
+// Format a positive floating point number like C's `printf`.
+fn float(n: f64, conversion: char, spec: &Spec) -> String {
+    let upper = conversion.is_uppercase();
+    if !n.is_finite() {
+        let text = if n.is_nan() { "nan" } else { "inf" };
+        return if upper { text.to_uppercase() } else { text.into() };
+    }
+
+    let precision = spec.precision.unwrap_or(6);
+    let text = match conversion.to_ascii_lowercase() {
+        'f' => {
+            let mut text = format!("{:.*}", precision, n);
+            if spec.alternate && precision == 0 {
+                text.push('.');
+            }
+            text
+        },
+        'e' => exponent(n, precision, spec.alternate),
+        _ => {
+            // `%g` uses the shorter of `%e` and `%f`, with the precision
+            // counting significant digits.
+            let precision = precision.max(1);
+            let e = exponent(n, precision - 1, false);
+            let x: i64 = e[e.find('e').unwrap() + 1..].parse().unwrap();
+            let mut text = if x < -4 || x >= precision as i64 {
+                e
+            } else {
+                format!("{:.*}", (precision as i64 - 1 - x) as usize, n)
+            };
+            if !spec.alternate {
+                let end = text.find('e').unwrap_or(text.len());
+                let (mantissa, exponent) = text.split_at(end);
+                let mantissa = if mantissa.contains('.') {
+                    mantissa.trim_end_matches('0').trim_end_matches('.')
+                } else {
+                    mantissa
+                };
+                text = format!("{}{}", mantissa, exponent);
+            }
+            text
+        },
+    };
+
+    if upper { text.to_uppercase() } else { text }
+}
+
+// Format a number as `d.ddde±dd`.
+fn exponent(n: f64, precision: usize, alternate: bool) -> String {
+    let text = format!("{:.*e}", precision, n);
+    let (mantissa, exponent) = text.split_at(text.find('e').unwrap());
+    let exponent: i64 = exponent[1..].parse().unwrap();
+    let dot = if alternate && precision == 0 { "." } else { "" };
+    format!("{}{}e{}{:02}", mantissa, dot,
+            if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printf(text: &str, args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        String::from_utf8(format(text, &args).0).unwrap()
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!("hello world\n", printf("%s %s\\n", &["hello", "world"]));
+        assert_eq!("  a|b  |ab\n", printf("%3s|%-3s|%.2s\\n", &["a", "b", "abc"]));
+        assert_eq!("a\n", printf("%c\\n", &["abc"]));
+        assert_eq!("'a b'", printf("%q", &["a b"]));
+        assert_eq!("100%", printf("100%%", &[]));
+        assert_eq!("A\tB", printf("\\101\\tB", &[]));
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!("42 -42 +42", printf("%d %i %+d", &["42", "-42", "42"]));
+        assert_eq!("00042|42   |  042", printf("%05d|%-5d|%5.3d", &["42", "42", "42"]));
+        assert_eq!("52 2a 0X2A 052", printf("%o %x %#X %#o", &["42", "42", "42", "42"]));
+        assert_eq!("42 42 42", printf("%d %d %d", &["0x2a", "052", "'*"]));
+        assert_eq!("0", printf("%d", &[]));
+        assert_eq!("   42", printf("%*d", &["5", "42"]));
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!("3.141590", printf("%f", &["3.14159"]));
+        assert_eq!(" 3.14|-3.1", printf("%5.2f|%.1f", &["3.14159", "-3.14159"]));
+        assert_eq!("1.500000e+02 1.5E-03", printf("%e %.1E", &["150", "0.0015"]));
+        assert_eq!("100000 1e+06 0.0001 1.5", printf("%g %g %g %g", &["100000", "1000000",
+                                                                  "0.0001", "1.50"]));
+        assert_eq!("inf", printf("%f", &["inf"]));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!("a\nb", printf("%b", &["a\\nb"]));
+        assert_eq!("a", printf("%b%s", &["a\\cb", "c"]));
+        assert_eq!("A", printf("%b", &["\\0101"]));
+        assert_eq!(vec![0xff, b' '], format("%-2b", &["\\0377".into()]).0);
+    }
+
+    #[test]
+    fn reuse() {
+        assert_eq!("a=1\nb=2\n", printf("%s=%d\\n", &["a", "1", "b", "2"]));
+        assert_eq!("a=1\nb=0\n", printf("%s=%d\\n", &["a", "1", "b"]));
+        assert_eq!("x\n", printf("x\\n", &["a", "b"]));
+    }
+}
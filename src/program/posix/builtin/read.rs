@@ -0,0 +1,334 @@
+//! The `read` builtin, which reads a line of input into variables.
+//!
+//! Input is read a single byte at a time, so a loop like
+//! `while read -r line; do ...; done < file` sees every line exactly once.
+use std::{
+    ffi::CString,
+    io::Write,
+    time::{Duration, Instant},
+};
+use nix::{
+    poll::{poll, PollFd, PollFlags},
+    sys::{
+        termios::{tcgetattr, tcsetattr, LocalFlags, SetArg},
+        wait::WaitStatus,
+    },
+    unistd::{self, isatty, Pid},
+};
+use crate::{
+    program::{Result, IO, posix::ast, runtime::Runtime},
+    job::Jobs as JobsRef,
+};
+use super::Builtin;
+
+/// Line reading builtin.
+///
+/// ```sh
+/// read -r name rest
+/// read -p "continue? " -n 1 answer
+/// ```
+///
+/// The line is split into fields by `$IFS`, one for each name, with the
+/// rest of the line going to the last name, or to `$REPLY` without any
+/// names. Unless `-r` is given, a backslash escapes the character after it,
+/// and a backslash before a newline continues the line.
+///
+/// As extensions, `-p prompt` writes a prompt when reading from a terminal,
+/// `-t seconds` gives up after a timeout, `-n count` reads at most `count`
+/// characters, `-s` doesn't echo a terminal's input and `-d delim` reads up
+/// to `delim` instead of a newline.
+pub struct Read;
+
+impl Builtin for Read {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, runtime: Runtime)
+        -> Result<WaitStatus>
+    {
+        let args: Vec<String> = argv.iter().skip(1).map(|a| {
+            a.to_string_lossy().into_owned()
+        }).collect();
+        let options = match Options::parse(&args) {
+            Ok(options) => options,
+            Err(message) => {
+                writeln!(io.stderr(), "oursh: read: {}", message).ok();
+                return Ok(WaitStatus::Exited(Pid::this(), 2));
+            },
+        };
+        if let Some(name) = options.names.iter().find(|n| !ast::is_name(n)) {
+            writeln!(io.stderr(), "oursh: read: {}: invalid variable name", name).ok();
+            return Ok(WaitStatus::Exited(Pid::this(), 2));
+        }
+
+        let fd = io.stdin().0;
+        let terminal = isatty(fd).unwrap_or(false);
+        if let (Some(prompt), true) = (&options.prompt, terminal) {
+            io.stderr().write_all(prompt.as_bytes()).ok();
+        }
+
+        // Turn off echoing for `-s`, restoring it once the line is read.
+        let saved = if options.silent && terminal {
+            tcgetattr(fd).ok().map(|saved| {
+                let mut silent = saved.clone();
+                silent.local_flags.remove(LocalFlags::ECHO);
+                tcsetattr(fd, SetArg::TCSANOW, &silent).ok();
+                saved
+            })
+        } else {
+            None
+        };
+        let (line, end) = read_line(fd, &options);
+        if let Some(saved) = saved {
+            tcsetattr(fd, SetArg::TCSANOW, &saved).ok();
+        }
+
+        let ifs = runtime.borrow().get("IFS").unwrap_or_else(|| " \t\n".into());
+        let mut names = options.names.clone();
+        if names.is_empty() {
+            names.push("REPLY".into());
+        }
+        let fields = split(&line, ifs.as_bytes(), names.len());
+        let mut state = runtime.borrow_mut();
+        for (name, field) in names.iter().zip(fields.iter()) {
+            state.set(name, &String::from_utf8_lossy(field));
+        }
+
+        let code = match end {
+            End::Delimiter => 0,
+            End::Eof => 1,
+            // Like other shells, report a timeout as though it were a
+            // `SIGALRM`.
+            End::Timeout => 128 + 14,
+        };
+        Ok(WaitStatus::Exited(Pid::this(), code))
+    }
+}
+
+// The arguments of a single `read`.
+#[derive(Debug, Default)]
+struct Options {
+    raw: bool,
+    silent: bool,
+    prompt: Option<String>,
+    timeout: Option<Duration>,
+    count: Option<usize>,
+    delimiter: Option<u8>,
+    names: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> std::result::Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                break;
+            } else if !arg.starts_with('-') || arg == "-" {
+                options.names.push(arg.clone());
+                break;
+            }
+
+            let mut flags = arg[1..].char_indices();
+            while let Some((i, flag)) = flags.next() {
+                match flag {
+                    'r' => options.raw = true,
+                    's' => options.silent = true,
+                    'p' | 't' | 'n' | 'd' => {
+                        // The value is either the rest of this argument, or
+                        // the next one.
+                        let rest = &arg[i + 2..];
+                        let value = if rest.is_empty() {
+                            args.next().cloned().ok_or_else(|| {
+                                format!("-{}: option requires an argument", flag)
+                            })?
+                        } else {
+                            rest.to_string()
+                        };
+                        options.value(flag, value)?;
+                        break;
+                    },
+                    c => return Err(format!("-{}: invalid option", c)),
+                }
+            }
+        }
+        options.names.extend(args.cloned());
+        Ok(options)
+    }
+
+    fn value(&mut self, flag: char, value: String)
+        -> std::result::Result<(), String>
+    {
+        match flag {
+            'p' => self.prompt = Some(value),
+            't' => {
+                let seconds = value.parse::<f64>()
+                    .ok()
+                    .filter(|s| *s >= 0.0)
+                    .ok_or_else(|| format!("{}: invalid timeout", value))?;
+                self.timeout = Some(Duration::from_secs_f64(seconds));
+            },
+            'n' => {
+                let count = value.parse()
+                    .map_err(|_| format!("{}: invalid count", value))?;
+                self.count = Some(count);
+            },
+            _ => self.delimiter = Some(*value.as_bytes().first().unwrap_or(&0)),
+        }
+        Ok(())
+    }
+}
+
+// Why reading a line stopped.
+#[derive(Debug, PartialEq)]
+enum End {
+    Delimiter,
+    Eof,
+    Timeout,
+}
+
+// Read a line as bytes, each marked true when it was escaped by a
+// backslash.
+fn read_line(fd: i32, options: &Options) -> (Vec<(u8, bool)>, End) {
+    let delimiter = options.delimiter.unwrap_or(b'\n');
+    let deadline = options.timeout.map(|t| Instant::now() + t);
+    let mut line = vec![];
+    let mut escaped = false;
+    let mut characters = 0;
+
+    loop {
+        if options.count.map_or(false, |n| characters >= n) {
+            return (line, End::Delimiter);
+        }
+
+        if let Some(deadline) = deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+            match poll(&mut fds, left.as_millis() as i32) {
+                Ok(0) => return (line, End::Timeout),
+                Ok(_) => {},
+                Err(_) => return (line, End::Eof),
+            }
+        }
+
+        let mut byte = [0];
+        match unistd::read(fd, &mut byte) {
+            Ok(1) => {},
+            _ => return (line, End::Eof),
+        }
+        let byte = byte[0];
+
+        // Only count the first byte of each UTF-8 character.
+        if byte & 0xc0 != 0x80 {
+            characters += 1;
+        }
+
+        if escaped {
+            escaped = false;
+            // A backslash and newline are removed, continuing the line.
+            if byte != b'\n' {
+                line.push((byte, true));
+            }
+        } else if byte == b'\\' && !options.raw {
+            escaped = true;
+        } else if byte == delimiter {
+            return (line, End::Delimiter);
+        } else {
+            line.push((byte, false));
+        }
+    }
+}
+
+/// Split a line into at most `n` fields by the bytes of `ifs`, with the
+/// last field keeping the rest of the line.
+///
+/// Escaped bytes never split fields. Any whitespace in `ifs` is trimmed
+/// from the ends of the line, and runs of it separate fields, while every
+/// other byte of `ifs` separates fields on its own.
+fn split(line: &[(u8, bool)], ifs: &[u8], n: usize) -> Vec<Vec<u8>> {
+    let is_ifs = |&(b, escaped): &(u8, bool)| !escaped && ifs.contains(&b);
+    let is_white = |c: &(u8, bool)| is_ifs(c) && b" \t\n".contains(&c.0);
+    let bytes = |s: &[(u8, bool)]| s.iter().map(|&(b, _)| b).collect::<Vec<_>>();
+
+    // Without `$IFS` there's no splitting at all.
+    if ifs.is_empty() {
+        let mut fields = vec![bytes(line)];
+        fields.resize(n, vec![]);
+        return fields;
+    }
+
+    let mut fields = vec![];
+    let mut rest = line;
+    while rest.first().map_or(false, is_white) {
+        rest = &rest[1..];
+    }
+    while fields.len() + 1 < n && !rest.is_empty() {
+        let end = rest.iter().position(is_ifs).unwrap_or(rest.len());
+        fields.push(bytes(&rest[..end]));
+        rest = &rest[end..];
+
+        // Skip the separator, which is whitespace around at most one
+        // other `$IFS` byte.
+        while rest.first().map_or(false, is_white) {
+            rest = &rest[1..];
+        }
+        if rest.first().map_or(false, is_ifs) {
+            rest = &rest[1..];
+            while rest.first().map_or(false, is_white) {
+                rest = &rest[1..];
+            }
+        }
+    }
+    while rest.last().map_or(false, is_white) {
+        rest = &rest[..rest.len() - 1];
+    }
+    fields.push(bytes(rest));
+    fields.resize(n, vec![]);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(line: &str, ifs: &str, n: usize) -> Vec<String> {
+        let line: Vec<_> = line.bytes().map(|b| (b, false)).collect();
+        split(&line, ifs.as_bytes(), n).into_iter().map(|f| {
+            String::from_utf8(f).unwrap()
+        }).collect()
+    }
+
+    #[test]
+    fn split_whitespace() {
+        assert_eq!(vec!["a", "b  c"], fields("  a  b  c  ", " \t\n", 2));
+        assert_eq!(vec!["a", "b", ""], fields("a b", " \t\n", 3));
+        assert_eq!(vec!["a b"], fields(" a b ", " \t\n", 1));
+        assert_eq!(vec![" a b "], fields(" a b ", "", 1));
+    }
+
+    #[test]
+    fn split_other() {
+        assert_eq!(vec!["a", "", "c"], fields("a::c", ":", 3));
+        assert_eq!(vec!["a", "b", "c"], fields("a : b:c", ": ", 3));
+        assert_eq!(vec!["", "b:c"], fields(":b:c", ":", 2));
+    }
+
+    #[test]
+    fn split_escaped() {
+        let line = vec![(b'a', false), (b' ', true), (b'b', false),
+                        (b' ', false), (b'c', false)];
+        assert_eq!(vec![b"a b".to_vec(), b"c".to_vec()],
+                   split(&line, b" ", 2));
+    }
+
+    #[test]
+    fn options() {
+        let args: Vec<String> = ["-rs", "-p", "> ", "-n1", "-d:", "a", "b"]
+            .iter().map(|a| a.to_string()).collect();
+        let options = Options::parse(&args).unwrap();
+        assert!(options.raw && options.silent);
+        assert_eq!(Some("> ".into()), options.prompt);
+        assert_eq!(Some(1), options.count);
+        assert_eq!(Some(b':'), options.delimiter);
+        assert_eq!(vec!["a", "b"], options.names);
+        assert!(Options::parse(&["-z".to_string()]).is_err());
+        assert!(Options::parse(&["-t".to_string()]).is_err());
+    }
+}
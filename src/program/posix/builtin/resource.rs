@@ -0,0 +1,325 @@
+//! The `umask`, `ulimit` and `times` builtins, which change or report the
+//! resources of the shell process itself.
+use std::{
+    ffi::CString,
+    io::Write,
+};
+use nix::{
+    unistd::Pid,
+    sys::{
+        stat::{umask, Mode},
+        wait::WaitStatus,
+    },
+};
+use nix_resource::{
+    libc::rlim_t,
+    sys::{
+        resource::{getrlimit, getrusage, setrlimit, Resource, UsageWho, RLIM_INFINITY},
+        time::TimeVal,
+    },
+};
+use crate::{
+    program::{Result, IO, runtime::Runtime},
+    job::Jobs as JobsRef,
+};
+use super::{Builtin, output};
+
+/// File creation mask (`umask`) builtin.
+///
+/// ```sh
+/// umask 022
+/// umask -S u=rwx,g=rx,o=
+/// umask g+w
+/// ```
+///
+/// Without a mode the mask is printed in octal, or with `-S` as the
+/// permissions it allows. Modes are either octal, or symbolic like `chmod`,
+/// where they're applied to the permissions allowed by the mask.
+pub struct Umask;
+
+impl Builtin for Umask {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, _: Runtime)
+        -> Result<WaitStatus>
+    {
+        let mut args: Vec<String> = argv.iter().skip(1).map(|a| {
+            a.to_string_lossy().into_owned()
+        }).collect();
+        let symbolic = args.first().map_or(false, |a| a == "-S");
+        if symbolic {
+            args.remove(0);
+        }
+
+        // The mask can only be read by setting it.
+        let mask = umask(Mode::empty());
+        umask(mask);
+        let mask = mask.bits() as u32 & 0o777;
+
+        match args.as_slice() {
+            [] if symbolic => {
+                let text = format!("{}\n", symbolic_mode(!mask & 0o777));
+                Ok(output("umask", io, text.as_bytes()))
+            },
+            [] => Ok(output("umask", io, format!("{:04o}\n", mask).as_bytes())),
+            [mode] => match parse_mode(mode, mask) {
+                Some(mask) => {
+                    umask(Mode::from_bits_truncate(mask as _));
+                    Ok(WaitStatus::Exited(Pid::this(), 0))
+                },
+                None => {
+                    writeln!(io.stderr(), "oursh: umask: {}: invalid mode", mode).ok();
+                    Ok(WaitStatus::Exited(Pid::this(), 1))
+                },
+            },
+            _ => {
+                writeln!(io.stderr(), "oursh: umask: too many arguments").ok();
+                Ok(WaitStatus::Exited(Pid::this(), 2))
+            },
+        }
+    }
+}
+
+// Parse an octal or symbolic mode, returning the new mask.
+fn parse_mode(mode: &str, mask: u32) -> Option<u32> {
+    if mode.bytes().all(|b| b'0' <= b && b <= b'7') {
+        return u32::from_str_radix(mode, 8).ok().filter(|m| *m <= 0o777);
+    }
+
+    let mut allowed = !mask & 0o777;
+    for clause in mode.split(',') {
+        let op = clause.find(|c| c == '+' || c == '-' || c == '=')?;
+        let mut who = 0;
+        for c in clause[..op].chars() {
+            who |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => return None,
+            };
+        }
+        if who == 0 {
+            who = 0o777;
+        }
+
+        // Each action is an operator followed by permissions, like `+x-w`.
+        let mut actions = clause[op..].chars().peekable();
+        while let Some(op) = actions.next() {
+            let mut bits = 0;
+            while let Some(&c) = actions.peek() {
+                bits |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' | 'X' => 0o111,
+                    's' | 't' => 0,
+                    // Copy the permissions of a class, like `g=u`.
+                    'u' => (allowed >> 6 & 7) * 0o111,
+                    'g' => (allowed >> 3 & 7) * 0o111,
+                    'o' => (allowed & 7) * 0o111,
+                    '+' | '-' | '=' => break,
+                    _ => return None,
+                };
+                actions.next();
+            }
+            allowed = match op {
+                '+' => allowed | (bits & who),
+                '-' => allowed & !(bits & who),
+                '=' => (allowed & !who) | (bits & who),
+                _ => return None,
+            };
+        }
+    }
+    Some(!allowed & 0o777)
+}
+
+// Write permissions like `u=rwx,g=rx,o=rx`.
+fn symbolic_mode(allowed: u32) -> String {
+    let class = |shift: u32| {
+        let bits = allowed >> shift & 7;
+        [(4, 'r'), (2, 'w'), (1, 'x')].iter()
+            .filter(|(bit, _)| bits & bit != 0)
+            .map(|(_, c)| *c)
+            .collect::<String>()
+    };
+    format!("u={},g={},o={}", class(6), class(3), class(0))
+}
+
+/// Resource limit (`ulimit`) builtin.
+///
+/// ```sh
+/// ulimit -a
+/// ulimit -n 1024
+/// ulimit -Hc unlimited
+/// ```
+///
+/// Prints or sets the soft limit of a resource, the file size by default,
+/// or the hard limit with `-H`. Without `-H` or `-S` both limits are set.
+pub struct Ulimit;
+
+// Each resource's flag, name, units and size of those units, and the
+// resource itself.
+const RESOURCES: &[(char, &str, &str, rlim_t, Resource)] = &[
+    ('c', "core file size", "blocks", 512, Resource::RLIMIT_CORE),
+    ('d', "data seg size", "kbytes", 1024, Resource::RLIMIT_DATA),
+    ('f', "file size", "blocks", 512, Resource::RLIMIT_FSIZE),
+    ('l', "max locked memory", "kbytes", 1024, Resource::RLIMIT_MEMLOCK),
+    ('m', "max memory size", "kbytes", 1024, Resource::RLIMIT_RSS),
+    ('n', "open files", "", 1, Resource::RLIMIT_NOFILE),
+    ('s', "stack size", "kbytes", 1024, Resource::RLIMIT_STACK),
+    ('t', "cpu time", "seconds", 1, Resource::RLIMIT_CPU),
+    ('u', "max user processes", "", 1, Resource::RLIMIT_NPROC),
+    ('v', "virtual memory", "kbytes", 1024, Resource::RLIMIT_AS),
+];
+
+impl Builtin for Ulimit {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, _: Runtime)
+        -> Result<WaitStatus>
+    {
+        let (mut hard, mut soft, mut all) = (false, false, false);
+        let mut resource = 'f';
+        let mut operands = vec![];
+        for arg in argv.iter().skip(1).map(|a| a.to_string_lossy()) {
+            if !arg.starts_with('-') || arg.len() < 2 {
+                operands.push(arg.into_owned());
+                continue;
+            }
+            for flag in arg[1..].chars() {
+                match flag {
+                    'H' => hard = true,
+                    'S' => soft = true,
+                    'a' => all = true,
+                    c if RESOURCES.iter().any(|r| r.0 == c) => resource = c,
+                    c => return Ok(failure(io, &format!("-{}: invalid option", c), 2)),
+                }
+            }
+        }
+
+        let show = |r: &(char, &str, &str, rlim_t, Resource)| {
+            match getrlimit(r.4) {
+                Ok((soft, hard_limit)) => {
+                    let n = if hard { hard_limit } else { soft };
+                    if n == RLIM_INFINITY {
+                        "unlimited".into()
+                    } else {
+                        (n / r.3).to_string()
+                    }
+                },
+                Err(_) => "unknown".into(),
+            }
+        };
+
+        let r = RESOURCES.iter().find(|r| r.0 == resource).expect("resource");
+        match operands.as_slice() {
+            [] if all => {
+                let mut text = String::new();
+                for r in RESOURCES {
+                    let units = if r.2.is_empty() {
+                        format!("(-{})", r.0)
+                    } else {
+                        format!("({}, -{})", r.2, r.0)
+                    };
+                    text += &format!("{:<20}{:>16} {}\n", r.1, units, show(r));
+                }
+                Ok(output("ulimit", io, text.as_bytes()))
+            },
+            [] => Ok(output("ulimit", io, format!("{}\n", show(r)).as_bytes())),
+            [value] => {
+                let value = if value == "unlimited" {
+                    RLIM_INFINITY
+                } else {
+                    match value.parse::<rlim_t>() {
+                        Ok(n) => n.saturating_mul(r.3),
+                        Err(_) => {
+                            let message = format!("{}: invalid limit", value);
+                            return Ok(failure(io, &message, 1));
+                        },
+                    }
+                };
+                // Only the limit which is given changes, without `-H` or
+                // `-S` that's both of them.
+                let limits = getrlimit(r.4).map(|(current_soft, current_hard)| {
+                    match (soft, hard) {
+                        (true, false) => (value, current_hard),
+                        (false, true) => (current_soft, value),
+                        _ => (value, value),
+                    }
+                });
+                match limits.and_then(|(soft, hard)| setrlimit(r.4, soft, hard)) {
+                    Ok(()) => Ok(WaitStatus::Exited(Pid::this(), 0)),
+                    Err(e) => Ok(failure(io, &e.to_string(), 1)),
+                }
+            },
+            _ => Ok(failure(io, "too many arguments", 2)),
+        }
+    }
+}
+
+fn failure(io: IO, message: &str, code: i32) -> WaitStatus {
+    writeln!(io.stderr(), "oursh: ulimit: {}", message).ok();
+    WaitStatus::Exited(Pid::this(), code)
+}
+
+/// Process times (`times`) builtin.
+///
+/// Prints the user and system time used by the shell on the first line,
+/// and by the commands it has waited for on the second.
+pub struct Times;
+
+impl Builtin for Times {
+    fn run(_: Vec<CString>, io: IO, _: JobsRef, _: Runtime)
+        -> Result<WaitStatus>
+    {
+        let times = getrusage(UsageWho::RUSAGE_SELF).and_then(|shell| {
+            let children = getrusage(UsageWho::RUSAGE_CHILDREN)?;
+            Ok([shell.user_time(), shell.system_time(),
+                children.user_time(), children.system_time()])
+        });
+
+        match times {
+            Ok(times) => {
+                let text = format!("{} {}\n{} {}\n", time(times[0]), time(times[1]),
+                                   time(times[2]), time(times[3]));
+                Ok(output("times", io, text.as_bytes()))
+            },
+            Err(e) => {
+                writeln!(io.stderr(), "oursh: times: {}", e).ok();
+                Ok(WaitStatus::Exited(Pid::this(), 1))
+            },
+        }
+    }
+}
+
+// Write a time like `0m1.250s`.
+fn time(time: TimeVal) -> String {
+    let seconds = time.tv_sec() as f64 + time.tv_usec() as f64 / 1e6;
+    format!("{}m{:.3}s", (seconds / 60.) as u64, seconds % 60.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn octal_modes() {
+        assert_eq!(Some(0o022), parse_mode("022", 0o077));
+        assert_eq!(Some(0o7), parse_mode("7", 0o022));
+        assert_eq!(None, parse_mode("1000", 0o022));
+    }
+
+    #[test]
+    fn symbolic_modes() {
+        assert_eq!(Some(0o027), parse_mode("u=rwx,g=rx,o=", 0o000));
+        assert_eq!(Some(0o002), parse_mode("g+w", 0o022));
+        assert_eq!(Some(0o222), parse_mode("-w", 0o000));
+        assert_eq!(Some(0o000), parse_mode("a+rwx", 0o777));
+        assert_eq!(Some(0o020), parse_mode("g=u-w", 0o000));
+        assert_eq!(None, parse_mode("z+r", 0o022));
+        assert_eq!(None, parse_mode("u", 0o022));
+        assert_eq!("u=rwx,g=rx,o=", symbolic_mode(0o750));
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!("0m0.000s", time(TimeVal::new(0, 0)));
+        assert_eq!("1m1.250s", time(TimeVal::new(61, 250_000)));
+    }
+}
@@ -0,0 +1,273 @@
+//! The `test` builtin, also known as `[`.
+//!
+//! Expressions of one to four arguments follow the POSIX rules, which decide
+//! what each argument is by how many there are, so `test -n` is true and
+//! `test ! =` compares nothing. Longer expressions are parsed with `-o`
+//! binding looser than `-a`, which binds looser than `!`.
+use std::{
+    ffi::CString,
+    fs::{self, Metadata},
+    io::Write,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+};
+use nix::{
+    unistd::{access, isatty, AccessFlags, Pid},
+    sys::wait::WaitStatus,
+};
+use crate::{
+    program::{Result, IO, runtime::Runtime},
+    job::Jobs as JobsRef,
+};
+use super::Builtin;
+
+/// Conditional expression builtin, `test` or `[`.
+///
+/// Exits with 0 when the expression is true, 1 when it's false and 2 when
+/// it isn't a valid expression.
+pub struct Test;
+
+impl Builtin for Test {
+    fn run(argv: Vec<CString>, io: IO, _: JobsRef, _: Runtime)
+        -> Result<WaitStatus>
+    {
+        let name = argv[0].to_string_lossy().into_owned();
+        let mut args: Vec<String> = argv.iter().skip(1).map(|a| {
+            a.to_string_lossy().into_owned()
+        }).collect();
+
+        let result = if name == "[" && args.last().map(|a| a.as_str()) != Some("]") {
+            Err("missing `]'".into())
+        } else {
+            if name == "[" {
+                args.pop();
+            }
+            let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+            evaluate(&args)
+        };
+
+        let code = match result {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(message) => {
+                writeln!(io.stderr(), "oursh: {}: {}", name, message).ok();
+                2
+            },
+        };
+        Ok(WaitStatus::Exited(Pid::this(), code))
+    }
+}
+
+/// Evaluate the arguments of `test` as a conditional expression.
+///
+/// ```
+/// use oursh::program::posix::builtin::test::evaluate;
+///
+/// assert_eq!(Ok(false), evaluate(&[]));
+/// assert_eq!(Ok(true), evaluate(&["-n"]));
+/// assert_eq!(Ok(true), evaluate(&["1", "-lt", "2", "-a", "!", "-z", "x"]));
+/// assert!(evaluate(&["1", "-lt", "a"]).is_err());
+/// ```
+pub fn evaluate(args: &[&str]) -> std::result::Result<bool, String> {
+    match args {
+        [] => Ok(false),
+        [a] => Ok(!a.is_empty()),
+        ["!", a] => Ok(a.is_empty()),
+        [op, a] if is_unary(op) => unary(op, a),
+        [_, _] => Err(format!("{}: unary operator expected", args[0])),
+        [a, op, b] if is_binary(op) => binary(a, op, b),
+        ["!", a, b] => evaluate(&[a, b]).map(|r| !r),
+        ["(", a, ")"] => evaluate(&[a]),
+        ["!", a, b, c] => evaluate(&[a, b, c]).map(|r| !r),
+        ["(", a, b, ")"] => evaluate(&[a, b]),
+        _ => {
+            let mut parser = Parser { args, position: 0 };
+            let result = parser.or()?;
+            match parser.peek() {
+                None => Ok(result),
+                Some(arg) => Err(format!("{}: unexpected argument", arg)),
+            }
+        },
+    }
+}
+
+// A recursive descent parser for expressions of any length.
+struct Parser<'a> {
+    args: &'a [&'a str],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.args.get(self.position).cloned()
+    }
+
+    fn next(&mut self) -> std::result::Result<&'a str, String> {
+        let arg = self.peek().ok_or_else(|| "argument expected".to_string())?;
+        self.position += 1;
+        Ok(arg)
+    }
+
+    // or: and ( -o and )*
+    fn or(&mut self) -> std::result::Result<bool, String> {
+        let mut result = self.and()?;
+        while self.peek() == Some("-o") {
+            self.position += 1;
+            // Both sides are always parsed, to find any errors.
+            result = self.and()? || result;
+        }
+        Ok(result)
+    }
+
+    // and: not ( -a not )*
+    fn and(&mut self) -> std::result::Result<bool, String> {
+        let mut result = self.not()?;
+        while self.peek() == Some("-a") {
+            self.position += 1;
+            result = self.not()? && result;
+        }
+        Ok(result)
+    }
+
+    // not: ! not | primary
+    fn not(&mut self) -> std::result::Result<bool, String> {
+        if self.peek() == Some("!") {
+            self.position += 1;
+            self.not().map(|r| !r)
+        } else {
+            self.primary()
+        }
+    }
+
+    // primary: ( or ) | operand binary operand | unary operand | operand
+    fn primary(&mut self) -> std::result::Result<bool, String> {
+        let arg = self.next()?;
+        let rest = &self.args[self.position..];
+
+        if rest.len() >= 2 && is_binary(rest[0]) {
+            self.position += 2;
+            binary(arg, rest[0], rest[1])
+        } else if arg == "(" {
+            let result = self.or()?;
+            match self.next() {
+                Ok(")") => Ok(result),
+                _ => Err("missing `)'".into()),
+            }
+        } else if is_unary(arg) && !rest.is_empty() {
+            self.position += 1;
+            unary(arg, rest[0])
+        } else {
+            Ok(!arg.is_empty())
+        }
+    }
+}
+
+fn is_unary(op: &str) -> bool {
+    match op {
+        "-b" | "-c" | "-d" | "-e" | "-f" | "-g" | "-h" | "-k" | "-L" |
+        "-n" | "-p" | "-r" | "-S" | "-s" | "-t" | "-u" | "-w" | "-x" |
+        "-z" => true,
+        _ => false,
+    }
+}
+
+fn is_binary(op: &str) -> bool {
+    match op {
+        "=" | "!=" | "-eq" | "-ne" | "-gt" | "-ge" | "-lt" | "-le" => true,
+        _ => false,
+    }
+}
+
+fn unary(op: &str, operand: &str) -> std::result::Result<bool, String> {
+    // File tests follow symbolic links, except the ones for links.
+    let metadata = || fs::metadata(operand).ok();
+    let mode = |bits: u32| metadata().map_or(false, |m| m.mode() & bits != 0);
+    let kind = |f: fn(&Metadata) -> bool| metadata().map_or(false, |m| f(&m));
+
+    Ok(match op {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-e" => metadata().is_some(),
+        "-f" => kind(|m| m.is_file()),
+        "-d" => kind(|m| m.is_dir()),
+        "-b" => kind(|m| m.file_type().is_block_device()),
+        "-c" => kind(|m| m.file_type().is_char_device()),
+        "-p" => kind(|m| m.file_type().is_fifo()),
+        "-S" => kind(|m| m.file_type().is_socket()),
+        "-s" => kind(|m| m.len() > 0),
+        "-h" | "-L" => fs::symlink_metadata(operand)
+                           .map_or(false, |m| m.file_type().is_symlink()),
+        "-u" => mode(0o4000),
+        "-g" => mode(0o2000),
+        "-k" => mode(0o1000),
+        "-r" => access(operand, AccessFlags::R_OK).is_ok(),
+        "-w" => access(operand, AccessFlags::W_OK).is_ok(),
+        "-x" => access(operand, AccessFlags::X_OK).is_ok(),
+        "-t" => isatty(integer(operand)? as i32).unwrap_or(false),
+        _ => return Err(format!("{}: unary operator expected", op)),
+    })
+}
+
+fn binary(left: &str, op: &str, right: &str)
+    -> std::result::Result<bool, String>
+{
+    Ok(match op {
+        "="   => left == right,
+        "!="  => left != right,
+        "-eq" => integer(left)? == integer(right)?,
+        "-ne" => integer(left)? != integer(right)?,
+        "-gt" => integer(left)? >  integer(right)?,
+        "-ge" => integer(left)? >= integer(right)?,
+        "-lt" => integer(left)? <  integer(right)?,
+        "-le" => integer(left)? <= integer(right)?,
+        _ => return Err(format!("{}: binary operator expected", op)),
+    })
+}
+
+fn integer(text: &str) -> std::result::Result<i64, String> {
+    text.trim().parse()
+        .map_err(|_| format!("{}: integer expression expected", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn argument_counts() {
+        assert_eq!(Ok(false), evaluate(&[""]));
+        assert_eq!(Ok(true), evaluate(&["-z"]));
+        assert_eq!(Ok(true), evaluate(&["!", ""]));
+        assert_eq!(Ok(false), evaluate(&["!", "="]));
+        assert_eq!(Ok(true), evaluate(&["-z", ""]));
+        assert_eq!(Ok(false), evaluate(&["!", "=", "="]));
+        assert_eq!(Ok(true), evaluate(&["(", "a", ")"]));
+        assert_eq!(Ok(true), evaluate(&["-a", "=", "-a"]));
+        assert_eq!(Ok(false), evaluate(&["!", "-n", "a"]));
+        assert_eq!(Ok(true), evaluate(&["(", "-n", "a", ")"]));
+        assert!(evaluate(&["a", "b"]).is_err());
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(Ok(true), evaluate(&["a", "=", "b", "-o", "1", "-eq", "1"]));
+        assert_eq!(Ok(false), evaluate(&["a", "=", "a", "-a", "1", "-ne", "1"]));
+        assert_eq!(Ok(true), evaluate(&["-n", "a", "-o", "-n", "", "-a", "-n", ""]));
+        assert_eq!(Ok(false), evaluate(&["(", "-n", "a", "-o", "-n", "",
+                                         ")", "-a", "-n", ""]));
+        assert_eq!(Ok(true), evaluate(&["!", "!", "-n", "a", "-a", "x"]));
+        assert!(evaluate(&["(", "-n", "a", "-a", "b"]).is_err());
+    }
+
+    #[test]
+    fn files() {
+        assert_eq!(Ok(true), evaluate(&["-e", "Cargo.toml"]));
+        assert_eq!(Ok(true), evaluate(&["-f", "Cargo.toml"]));
+        assert_eq!(Ok(false), evaluate(&["-d", "Cargo.toml"]));
+        assert_eq!(Ok(true), evaluate(&["-d", "src"]));
+        assert_eq!(Ok(true), evaluate(&["-s", "Cargo.toml"]));
+        assert_eq!(Ok(true), evaluate(&["-r", "Cargo.toml"]));
+        assert_eq!(Ok(true), evaluate(&["-c", "/dev/null"]));
+        assert_eq!(Ok(false), evaluate(&["-e", "/does/not/exist"]));
+        assert_eq!(Ok(false), evaluate(&["-L", "Cargo.toml"]));
+    }
+}
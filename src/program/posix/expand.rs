@@ -0,0 +1,775 @@
+//! Word expansion, performed on the words of a command right before it runs.
+//!
+//! Following POSIX (3§2.6), words go through tilde expansion, parameter
+//! expansion, command substitution, field splitting and finally quote
+//! removal. Arithmetic expansion is not yet supported, and is left as
+//! written, and pathname expansion is not performed.
+//!
+//! Command substitutions, `$(...)` or `` `...` ``, run in a subshell, so
+//! `x=$({#!/bin/sh; echo 1})` captures the output of a block like any
+//! other command.
+//!
+//! ```
+//! use oursh::program::runtime::State;
+//! use oursh::program::posix::expand;
+//!
+//! let runtime = State::new("oursh").shared();
+//! runtime.borrow_mut().set("GREETING", "hello  world");
+//!
+//! assert_eq!(vec!["hello", "world"],
+//!            expand::fields("$GREETING", &runtime).unwrap());
+//! assert_eq!(vec!["hello  world"],
+//!            expand::fields("\"$GREETING\"", &runtime).unwrap());
+//! assert_eq!("hello", expand::string("${GREETING%% *}", &runtime).unwrap());
+//! ```
+
+use std::{
+    env,
+    cell::RefCell,
+    io::{self, Read, Write},
+    process,
+    rc::Rc,
+};
+use nix::{
+    sys::wait::waitpid,
+    unistd::{close, fork, pipe, ForkResult},
+};
+use crate::{
+    job::Fd,
+    program::{
+        Result, Error, IO, Program as ProgramTrait, Run, exit_status,
+        runtime::Runtime,
+    },
+};
+use super::{Program, ast::is_name};
+
+/// Expand a word into any number of fields, like the arguments of a command.
+pub fn fields(word: &str, runtime: &Runtime) -> Result<Vec<String>> {
+    let mut expander = Expander::new(runtime, true);
+    expander.word(word)?;
+    Ok(expander.finish())
+}
+
+/// Expand a word into a single string, without field splitting, like the
+/// value of an assignment.
+pub fn string(word: &str, runtime: &Runtime) -> Result<String> {
+    let mut expander = Expander::new(runtime, false);
+    expander.word(word)?;
+    Ok(expander.finish().join(" "))
+}
+
+/// Expand the parameters of some text, like the body of a here-document.
+///
+/// Quotes are kept as written, and a `\` only escapes a `$`, `` ` ``, `\`
+/// or newline.
+pub fn text(text: &str, runtime: &Runtime) -> Result<String> {
+    let mut expander = Expander::new(runtime, false);
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '\\' => {
+                match rest.chars().next() {
+                    Some(c @ '$') | Some(c @ '`') | Some(c @ '\\') => {
+                        expander.push(&c.to_string());
+                        rest = &rest[1..];
+                    },
+                    Some('\n') => rest = &rest[1..],
+                    _ => expander.push("\\"),
+                }
+            },
+            '$' => rest = expander.dollar(rest, true)?,
+            c => expander.push(&c.to_string()),
+        }
+    }
+    Ok(expander.finish().join(" "))
+}
+
+/// Returns true if the (shell-style) pattern matches the whole text.
+///
+/// Patterns may contain `*`, `?` and bracket expressions like `[a-z]` or
+/// `[!0-9]`, any other character matches itself unless escaped with `\`.
+///
+/// ```
+/// use oursh::program::posix::expand::matches;
+///
+/// assert!(matches("*.rs", "main.rs"));
+/// assert!(matches("[!.]?", "ab"));
+/// assert!(!matches("\\*", "a"));
+/// ```
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => {
+            (0..=text.len()).any(|i| match_from(&pattern[1..], &text[i..]))
+        },
+        Some('?') => !text.is_empty() && match_from(&pattern[1..], &text[1..]),
+        Some('[') => {
+            match (text.first(), bracket(&pattern[1..])) {
+                (Some(c), Some((set, negated, len))) => {
+                    let found = set.iter().any(|&(lo, hi)| lo <= *c && *c <= hi);
+                    found != negated && match_from(&pattern[len + 1..], &text[1..])
+                },
+                // An unclosed bracket is just a character.
+                (Some('['), None) => match_from(&pattern[1..], &text[1..]),
+                _ => false,
+            }
+        },
+        Some('\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) &&
+                match_from(&pattern[2..], &text[1..])
+        },
+        Some(c) => text.first() == Some(c) && match_from(&pattern[1..], &text[1..]),
+    }
+}
+
+// Parse a bracket expression, after the `[`, returning the ranges of the
+// set, if it's negated, and the length of the expression up to its `]`.
+fn bracket(pattern: &[char]) -> Option<(Vec<(char, char)>, bool, usize)> {
+    let mut i = 0;
+    let negated = match pattern.first() {
+        Some('!') | Some('^') => { i += 1; true },
+        _ => false,
+    };
+    let mut set = vec![];
+    let start = i;
+    while i < pattern.len() {
+        match pattern[i] {
+            ']' if i > start => return Some((set, negated, i + 1)),
+            c if pattern.get(i + 1) == Some(&'-') &&
+                 pattern.get(i + 2).map_or(false, |&c| c != ']') => {
+                set.push((c, pattern[i + 2]));
+                i += 3;
+            },
+            c => {
+                set.push((c, c));
+                i += 1;
+            },
+        }
+    }
+    None
+}
+
+// Return the index of the `close` which matches an already consumed `open`,
+// skipping quotes and escapes.
+fn closing(text: &str, open: char, close: char) -> Option<usize> {
+    let mut depth = 1;
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => { chars.next(); },
+            '\'' => { chars.find(|&(_, c)| c == '\''); },
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => { chars.next(); },
+                        '"' => break,
+                        _ => {},
+                    }
+                }
+            },
+            c if c == open => depth += 1,
+            c if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
+// The state of the expansion of a single word.
+struct Expander<'a> {
+    runtime: &'a Runtime,
+    // Perform field splitting on unquoted expansions.
+    split: bool,
+    // The complete fields so far.
+    fields: Vec<String>,
+    // The field being built, and if it should be kept even when empty.
+    current: String,
+    keep: bool,
+}
+
+impl<'a> Expander<'a> {
+    fn new(runtime: &'a Runtime, split: bool) -> Self {
+        Expander {
+            runtime,
+            split,
+            fields: vec![],
+            current: String::new(),
+            keep: false,
+        }
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        self.delimit();
+        self.fields
+    }
+
+    // Push some literal text onto the current field.
+    fn push(&mut self, text: &str) {
+        self.current.push_str(text);
+        self.keep = true;
+    }
+
+    // End the current field, if there is one.
+    fn delimit(&mut self) {
+        if self.keep || !self.current.is_empty() {
+            self.fields.push(self.current.split_off(0));
+        }
+        self.keep = false;
+    }
+
+    // Push the result of an expansion onto the current field, splitting it
+    // into more fields when unquoted.
+    fn push_expansion(&mut self, value: &str, quoted: bool) {
+        if quoted || !self.split {
+            self.current.push_str(value);
+            return;
+        }
+
+        let ifs = self.runtime.borrow().get("IFS")
+            .unwrap_or_else(|| " \t\n".into());
+        for c in value.chars() {
+            if !ifs.contains(c) {
+                self.current.push(c);
+                self.keep = true;
+            } else if c.is_whitespace() {
+                // Runs of whitespace delimit only a single field.
+                if !self.current.is_empty() {
+                    self.delimit();
+                }
+            } else {
+                self.keep = true;
+                self.delimit();
+            }
+        }
+    }
+
+    fn word(&mut self, word: &str) -> Result<()> {
+        let mut rest = self.tilde(word);
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '\\' => {
+                    match rest.chars().next() {
+                        // Escaped newlines are removed entirely.
+                        Some('\n') => {
+                            self.keep = true;
+                            rest = &rest[1..];
+                        },
+                        Some(c) => {
+                            self.push(&c.to_string());
+                            rest = &rest[c.len_utf8()..];
+                        },
+                        None => self.push("\\"),
+                    }
+                },
+                '\'' => {
+                    let end = rest.find('\'').unwrap_or(rest.len());
+                    self.push(&rest[..end]);
+                    rest = &rest[(end + 1).min(rest.len())..];
+                },
+                '"' => {
+                    rest = self.double_quote(rest)?;
+                },
+                '$' => {
+                    rest = self.dollar(rest, false)?;
+                },
+                '`' => {
+                    rest = self.backtick(rest, false)?;
+                },
+                c => {
+                    self.push(&c.to_string());
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // Expand a leading `~` into the home directory.
+    fn tilde<'w>(&mut self, word: &'w str) -> &'w str {
+        if word == "~" || word.starts_with("~/") {
+            if let Ok(home) = env::var("HOME") {
+                self.push(&home);
+                return &word[1..];
+            }
+        }
+        word
+    }
+
+    // Expand the inside of a double quote, returning the rest of the word
+    // after the closing quote.
+    fn double_quote<'w>(&mut self, mut rest: &'w str) -> Result<&'w str> {
+        // A lone "$@" expands to nothing at all without any arguments.
+        let no_args = self.runtime.borrow().args.is_empty();
+        if !(no_args && (rest.starts_with("$@\"") || rest.starts_with("${@}\""))) {
+            self.keep = true;
+        }
+
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '"' => return Ok(rest),
+                '\\' => {
+                    match rest.chars().next() {
+                        Some('\n') => rest = &rest[1..],
+                        Some(c @ '$') | Some(c @ '`') |
+                        Some(c @ '"') | Some(c @ '\\') => {
+                            self.current.push(c);
+                            rest = &rest[1..];
+                        },
+                        _ => self.current.push('\\'),
+                    }
+                },
+                '$' => rest = self.dollar(rest, true)?,
+                '`' => rest = self.backtick(rest, true)?,
+                c => self.current.push(c),
+            }
+        }
+        Ok(rest)
+    }
+
+    // Expand the parameter after a `$`, returning the rest of the word.
+    fn dollar<'w>(&mut self, rest: &'w str, quoted: bool) -> Result<&'w str> {
+        let mut chars = rest.chars();
+        match chars.next() {
+            Some('{') => {
+                match closing(&rest[1..], '{', '}') {
+                    Some(end) => {
+                        self.braced(&rest[1..end + 1], quoted)?;
+                        Ok(&rest[end + 2..])
+                    },
+                    None => {
+                        self.push("${");
+                        Ok(&rest[1..])
+                    }
+                }
+            },
+            // TODO: Arithmetic expansion.
+            Some('(') if rest.starts_with("((") => {
+                self.current.push('$');
+                Ok(rest)
+            },
+            Some('(') => {
+                match closing(&rest[1..], '(', ')') {
+                    Some(end) => {
+                        let output = substitute(&rest[1..end + 1], self.runtime)?;
+                        self.push_expansion(&output, quoted);
+                        Ok(&rest[end + 2..])
+                    },
+                    None => {
+                        self.push("$(");
+                        Ok(&rest[1..])
+                    }
+                }
+            },
+            Some('@') => {
+                self.arguments(quoted);
+                Ok(&rest[1..])
+            },
+            Some(c) if c.is_ascii_digit() || c != '_' && is_special(c) => {
+                let value = self.parameter(&c.to_string())?;
+                self.push_expansion(&value.unwrap_or_default(), quoted);
+                Ok(&rest[1..])
+            },
+            Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+                let end = rest.find(|c: char| !(c == '_' || c.is_ascii_alphanumeric()))
+                              .unwrap_or(rest.len());
+                let value = self.parameter(&rest[..end])?;
+                self.push_expansion(&value.unwrap_or_default(), quoted);
+                Ok(&rest[end..])
+            },
+            _ => {
+                self.current.push('$');
+                self.keep = true;
+                Ok(rest)
+            }
+        }
+    }
+
+    // Substitute the command after a `` ` ``, up to the next unescaped
+    // `` ` ``, returning the rest of the word. Inside, a `\` only escapes a
+    // `$`, `` ` `` or `\`.
+    fn backtick<'w>(&mut self, rest: &'w str, quoted: bool) -> Result<&'w str> {
+        let mut command = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '`' => {
+                    let output = substitute(&command, self.runtime)?;
+                    self.push_expansion(&output, quoted);
+                    return Ok(&rest[i + 1..]);
+                },
+                '\\' => match chars.next() {
+                    Some((_, c @ '$')) | Some((_, c @ '`')) | Some((_, c @ '\\')) => {
+                        command.push(c);
+                    },
+                    Some((_, c)) => {
+                        command.push('\\');
+                        command.push(c);
+                    },
+                    None => command.push('\\'),
+                },
+                c => command.push(c),
+            }
+        }
+        self.push("`");
+        Ok(rest)
+    }
+
+    // Expand `$@`, which keeps each positional parameter as its own field
+    // when quoted.
+    fn arguments(&mut self, quoted: bool) {
+        let args = self.runtime.borrow().args.clone();
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                if quoted || self.split {
+                    self.keep = true;
+                    self.delimit();
+                } else {
+                    self.current.push(' ');
+                }
+            }
+            self.push_expansion(arg, quoted);
+        }
+    }
+
+    // Expand the inside of a `${...}` expansion.
+    fn braced(&mut self, body: &str, quoted: bool) -> Result<()> {
+        // String length, `${#name}`.
+        if body.len() > 1 && body.starts_with('#') && is_parameter(&body[1..]) {
+            let value = self.parameter(&body[1..])?.unwrap_or_default();
+            self.push_expansion(&value.chars().count().to_string(), quoted);
+            return Ok(());
+        }
+
+        let name_len = if body.starts_with(|c: char| c.is_ascii_digit()) {
+            body.find(|c: char| !c.is_ascii_digit()).unwrap_or(body.len())
+        } else if body.starts_with(|c: char| c == '_' || c.is_ascii_alphabetic()) {
+            body.find(|c: char| !(c == '_' || c.is_ascii_alphanumeric()))
+                .unwrap_or(body.len())
+        } else if body.starts_with(is_special) {
+            1
+        } else {
+            return Err(Error::Expansion {
+                parameter: body.into(),
+                message: "bad substitution".into(),
+            });
+        };
+        let (name, operation) = body.split_at(name_len);
+
+        if name == "@" && operation.is_empty() {
+            self.arguments(quoted);
+            return Ok(());
+        }
+
+        // Parameters which are unset or null (with a `:`) use the word.
+        let (colon, operation) = if operation.starts_with(':') {
+            (true, &operation[1..])
+        } else {
+            (false, operation)
+        };
+        let mut op = operation.chars();
+        let (op, word) = match op.next() {
+            Some(c @ '%') | Some(c @ '#') if op.next() == Some(c) => {
+                (&operation[..2], &operation[2..])
+            },
+            Some(_) => operation.split_at(1),
+            None => ("", ""),
+        };
+
+        let value = match op {
+            "" if !colon => self.parameter(name)?,
+            "-" | "=" | "?" | "+" => {
+                let value = self.lookup(name);
+                let unset = match value {
+                    Some(ref v) => colon && v.is_empty(),
+                    None => true,
+                };
+                match op {
+                    "-" if unset => Some(string(word, self.runtime)?),
+                    "=" if unset => {
+                        if !is_name(name) {
+                            return Err(Error::Expansion {
+                                parameter: name.into(),
+                                message: "cannot assign in this way".into(),
+                            });
+                        }
+                        let word = string(word, self.runtime)?;
+                        self.runtime.borrow_mut().set(name, &word);
+                        Some(word)
+                    },
+                    "?" if unset => {
+                        let message = string(word, self.runtime)?;
+                        return Err(Error::Expansion {
+                            parameter: name.into(),
+                            message: if message.is_empty() {
+                                "parameter null or not set".into()
+                            } else {
+                                message
+                            },
+                        });
+                    },
+                    "+" if unset => None,
+                    "+" => Some(string(word, self.runtime)?),
+                    _ => value,
+                }
+            },
+            "%" | "%%" | "#" | "##" if !colon => {
+                let value = self.parameter(name)?.unwrap_or_default();
+                let pattern = string(word, self.runtime)?;
+                Some(remove(&value, &pattern, op))
+            },
+            _ => {
+                return Err(Error::Expansion {
+                    parameter: body.into(),
+                    message: "bad substitution".into(),
+                });
+            },
+        };
+        self.push_expansion(&value.unwrap_or_default(), quoted);
+        Ok(())
+    }
+
+    // Look up the value of a parameter, `None` if it's unset.
+    fn lookup(&self, name: &str) -> Option<String> {
+        let state = self.runtime.borrow();
+        match name {
+            "@" => Some(state.args.join(" ")),
+            // Joined by the first character of `IFS`, a space when it's
+            // unset, or nothing at all when it's empty.
+            "*" => {
+                let separator: String = match state.get("IFS") {
+                    Some(ifs) => ifs.chars().take(1).collect(),
+                    None => " ".into(),
+                };
+                Some(state.args.join(&separator))
+            },
+            "#" => Some(state.args.len().to_string()),
+            "?" => Some(state.status.to_string()),
+            "-" => {
+                let mut flags = state.options.flags();
+                if state.interactive {
+                    flags.push('i');
+                }
+                Some(flags)
+            },
+            "$" => Some(state.pid.to_string()),
+            "!" => state.background.map(|pid| pid.to_string()),
+            "0" => Some(state.name.clone()),
+            n if n.starts_with(|c: char| c.is_ascii_digit()) => {
+                n.parse::<usize>().ok()
+                 .and_then(|i| state.args.get(i.wrapping_sub(1)).cloned())
+            },
+            name => state.get(name),
+        }
+    }
+
+    // Look up the value of a parameter, which is an error when it's unset
+    // and the `nounset` option is set.
+    fn parameter(&self, name: &str) -> Result<Option<String>> {
+        let value = self.lookup(name);
+        if value.is_none() && name != "*" && name != "@" &&
+           self.runtime.borrow().options.nounset
+        {
+            return Err(Error::Expansion {
+                parameter: name.into(),
+                message: "parameter not set".into(),
+            });
+        }
+        Ok(value)
+    }
+}
+
+// Run the command of a substitution in a subshell, returning its output
+// without any trailing newlines. Its status is kept for a command without
+// any words, like `x=$(false)`.
+//
+// Like other shells, NUL bytes are dropped from the output, since they can't
+// be part of an argument. Words are UTF-8 strings, so any other invalid
+// bytes are replaced by U+FFFD.
+fn substitute(command: &str, runtime: &Runtime) -> Result<String> {
+    let program = Program::parse(command.as_bytes())?;
+    let (read, write) = pipe()?;
+    let child = match fork()? {
+        ForkResult::Parent { child } => child,
+        ForkResult::Child => {
+            close(read).ok();
+            let jobs = Rc::new(RefCell::new(vec![]));
+            let io = IO([0, write, 2]);
+            let code = match program.run(false, io, jobs, runtime.clone()) {
+                Ok(status) => exit_status(&status),
+                Err(error) => {
+                    eprintln!("oursh: {}", error);
+                    error.status()
+                },
+            };
+            io::stdout().flush().ok();
+            process::exit(code);
+        },
+    };
+    close(write)?;
+    let mut output = vec![];
+    let result = Fd(read).read_to_end(&mut output);
+    close(read)?;
+    let status = waitpid(child, None)?;
+    result.map_err(Error::Read)?;
+    runtime.borrow_mut().substitution = Some(exit_status(&status));
+
+    output.retain(|b| *b != 0);
+    let output = String::from_utf8_lossy(&output);
+    Ok(output.trim_end_matches('\n').into())
+}
+
+// Remove the smallest or largest (for `%%` and `##`) matching suffix or
+// prefix of the value.
+fn remove(value: &str, pattern: &str, op: &str) -> String {
+    let mut bounds: Vec<usize> = value.char_indices().map(|(i, _)| i).collect();
+    bounds.push(value.len());
+    match op {
+        "#" | "##" => {
+            if op == "##" {
+                bounds.reverse();
+            }
+            for i in bounds {
+                if matches(pattern, &value[..i]) {
+                    return value[i..].into();
+                }
+            }
+        },
+        _ => {
+            if op == "%" {
+                bounds.reverse();
+            }
+            for i in bounds {
+                if matches(pattern, &value[i..]) {
+                    return value[..i].into();
+                }
+            }
+        },
+    }
+    value.into()
+}
+
+fn is_special(c: char) -> bool {
+    match c {
+        '@' | '*' | '#' | '?' | '-' | '$' | '!' | '0' => true,
+        _ => false,
+    }
+}
+
+fn is_parameter(text: &str) -> bool {
+    is_name(text) ||
+        !text.is_empty() && text.chars().all(|c| c.is_ascii_digit()) ||
+        text.len() == 1 && text.starts_with(is_special)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::program::runtime::State;
+    use super::*;
+
+    fn runtime() -> Runtime {
+        let runtime = State::new("oursh").shared();
+        {
+            let mut state = runtime.borrow_mut();
+            state.set("EMPTY", "");
+            state.set("FILE", "src/main.rs");
+            state.set("SPACES", " a  b ");
+            state.args = vec!["one".into(), "two three".into()];
+        }
+        runtime
+    }
+
+    #[test]
+    fn quotes() {
+        let runtime = runtime();
+        assert_eq!(vec!["a b"], fields("'a b'", &runtime).unwrap());
+        assert_eq!(vec!["a b"], fields("\"a b\"", &runtime).unwrap());
+        assert_eq!(vec!["a b"], fields("a\\ b", &runtime).unwrap());
+        assert_eq!(vec!["$FILE"], fields("'$FILE'", &runtime).unwrap());
+        assert_eq!(vec!["a\"b"], fields("\"a\\\"b\"", &runtime).unwrap());
+        assert_eq!(vec![""], fields("''", &runtime).unwrap());
+    }
+
+    #[test]
+    fn parameters() {
+        let runtime = runtime();
+        assert_eq!(vec!["src/main.rs"], fields("$FILE", &runtime).unwrap());
+        assert_eq!(vec!["src/main.rs!"], fields("${FILE}!", &runtime).unwrap());
+        assert_eq!(vec!["a", "b"], fields("$SPACES", &runtime).unwrap());
+        assert_eq!(vec![" a  b "], fields("\"$SPACES\"", &runtime).unwrap());
+        assert!(fields("$EMPTY", &runtime).unwrap().is_empty());
+        assert!(fields("$UNSET_FOR_SURE", &runtime).unwrap().is_empty());
+        assert_eq!(vec!["$"], fields("$", &runtime).unwrap());
+    }
+
+    #[test]
+    fn special_parameters() {
+        let runtime = runtime();
+        assert_eq!(vec!["2"], fields("$#", &runtime).unwrap());
+        assert_eq!(vec!["one"], fields("$1", &runtime).unwrap());
+        assert_eq!(vec!["oursh"], fields("$0", &runtime).unwrap());
+        assert_eq!(vec!["0"], fields("$?", &runtime).unwrap());
+        assert_eq!(vec!["one", "two", "three"], fields("$@", &runtime).unwrap());
+        assert_eq!(vec!["one", "two three"], fields("\"$@\"", &runtime).unwrap());
+        assert_eq!(vec!["one two three"], fields("\"$*\"", &runtime).unwrap());
+        runtime.borrow_mut().args.clear();
+        assert!(fields("\"$@\"", &runtime).unwrap().is_empty());
+    }
+
+    #[test]
+    fn operations() {
+        let runtime = runtime();
+        assert_eq!("d", string("${UNSET_FOR_SURE:-d}", &runtime).unwrap());
+        assert_eq!("d", string("${EMPTY:-d}", &runtime).unwrap());
+        assert_eq!("", string("${EMPTY-d}", &runtime).unwrap());
+        assert_eq!("alt", string("${FILE:+alt}", &runtime).unwrap());
+        assert_eq!("11", string("${#FILE}", &runtime).unwrap());
+        assert_eq!("src/main", string("${FILE%.*}", &runtime).unwrap());
+        assert_eq!("main.rs", string("${FILE##*/}", &runtime).unwrap());
+        assert_eq!("main.rs", string("${FILE#src/}", &runtime).unwrap());
+        assert!(string("${UNSET_FOR_SURE:?}", &runtime).is_err());
+        assert_eq!("set", string("${ASSIGNED:=set}", &runtime).unwrap());
+        assert_eq!(Some("set".into()), runtime.borrow().get("ASSIGNED"));
+    }
+
+    #[test]
+    fn nounset() {
+        let runtime = runtime();
+        runtime.borrow_mut().options.nounset = true;
+        assert!(fields("$UNSET_FOR_SURE", &runtime).is_err());
+        assert!(fields("$3", &runtime).is_err());
+        assert!(fields("${UNSET_FOR_SURE:-ok}", &runtime).is_ok());
+        assert!(fields("$EMPTY", &runtime).is_ok());
+    }
+
+    #[test]
+    fn text() {
+        let runtime = runtime();
+        assert_eq!("print('src/main.rs')\n",
+                   super::text("print('$FILE')\n", &runtime).unwrap());
+        assert_eq!("\" a  b \" $FILE \\n",
+                   super::text("\"$SPACES\" \\$FILE \\n", &runtime).unwrap());
+        assert_eq!("one two three", super::text("$@", &runtime).unwrap());
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(matches("*", ""));
+        assert!(matches("a*c", "abbbc"));
+        assert!(!matches("a*c", "abbb"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[!a-c]x", "bx"));
+    }
+}
@@ -0,0 +1,119 @@
+//! The interpreters of `{#lang ...}` blocks, registered with the `lang`
+//! builtin.
+//!
+//! Each language has the command line of its interpreter, which is given
+//! the block's text as a file, or on its standard input. Ruby, Node, Python
+//! and Racket are registered to start with.
+//!
+//! ```
+//! use oursh::program::posix::hashlang::{self, Language};
+//!
+//! hashlang::set("awk", Language::new(vec!["awk".into(), "-f".into()]));
+//! assert_eq!(vec!["awk", "-f", "/dev/fd/3"],
+//!            hashlang::get("awk").unwrap().arguments("/dev/fd/3"));
+//! assert!(hashlang::remove("awk"));
+//! ```
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+};
+
+/// The interpreter of a language.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Language {
+    /// The command line of the interpreter, as a template where `{}` is
+    /// replaced by the file of the block's text. Without any `{}` the file
+    /// is added last.
+    pub argv: Vec<String>,
+    /// The extension of the block's file, for interpreters which need one.
+    pub extension: Option<String>,
+    /// Give the text on the interpreter's standard input, instead of as a
+    /// file.
+    pub stdin: bool,
+}
+
+impl Language {
+    /// A language reading its text from a file without an extension.
+    pub fn new(argv: Vec<String>) -> Self {
+        Language {
+            argv,
+            extension: None,
+            stdin: false,
+        }
+    }
+
+    /// The arguments of the interpreter, given the file of the text.
+    pub fn arguments(&self, file: &str) -> Vec<String> {
+        if self.stdin {
+            return self.argv.clone();
+        }
+
+        let mut arguments: Vec<String> = self.argv.iter().map(|word| {
+            word.replace("{}", file)
+        }).collect();
+        if !self.argv.iter().any(|word| word.contains("{}")) {
+            arguments.push(file.into());
+        }
+        arguments
+    }
+}
+
+thread_local! {
+    static LANGUAGES: RefCell<HashMap<String, Language>> = {
+        let languages = ["ruby", "node", "python", "racket"].iter().map(|name| {
+            (name.to_string(), Language::new(vec![name.to_string()]))
+        }).collect();
+        RefCell::new(languages)
+    };
+}
+
+/// Register a language, replacing any language of the same name.
+pub fn set(name: &str, language: Language) {
+    LANGUAGES.with(|languages| languages.borrow_mut().insert(name.into(), language));
+}
+
+/// The named language.
+pub fn get(name: &str) -> Option<Language> {
+    LANGUAGES.with(|languages| languages.borrow().get(name).cloned())
+}
+
+/// Remove the named language, returning false if there wasn't one.
+pub fn remove(name: &str) -> bool {
+    LANGUAGES.with(|languages| languages.borrow_mut().remove(name).is_some())
+}
+
+/// Every language, sorted by name.
+pub fn all() -> Vec<(String, Language)> {
+    let mut all: Vec<_> = LANGUAGES.with(|languages| {
+        languages.borrow().iter().map(|(n, l)| (n.clone(), l.clone())).collect()
+    });
+    all.sort_by(|a, b| a.0.cmp(&b.0));
+    all
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn language(argv: &[&str]) -> Language {
+        Language::new(argv.iter().map(|w| w.to_string()).collect())
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(vec!["ruby", "f"], language(&["ruby"]).arguments("f"));
+        assert_eq!(vec!["jq", "-f", "f", "-r"],
+                   language(&["jq", "-f", "{}", "-r"]).arguments("f"));
+        assert_eq!(vec!["x", "--file=f"],
+                   language(&["x", "--file={}"]).arguments("f"));
+        let mut sql = language(&["sqlite3"]);
+        sql.stdin = true;
+        assert_eq!(vec!["sqlite3"], sql.arguments("f"));
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(Some(language(&["ruby"])), get("ruby"));
+        assert!(get("nonsense").is_none());
+    }
+}
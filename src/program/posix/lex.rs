@@ -9,7 +9,12 @@
 //! }
 //! ```
 
-use std::str::{self, CharIndices};
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    str::{self, CharIndices},
+};
+use crate::program::posix::alias;
 
 /// A result type wrapping a token with start and end locations.
 pub type Span<T, E> = Result<(usize, T, usize), E>;
@@ -18,6 +23,27 @@ pub type Span<T, E> = Result<(usize, T, usize), E>;
 #[derive(Debug)]
 pub enum Error {
     UnrecognizedChar(usize, char, usize),
+    /// A quote or expansion without its end, which may continue on the
+    /// next line.
+    Unterminated(usize, usize),
+    /// A `{#...}` language block, which isn't strict POSIX syntax.
+    Block(usize, usize),
+}
+
+thread_local! {
+    static STRICT: Cell<bool> = Cell::new(false);
+}
+
+/// Lex only strict POSIX syntax, where a `{#...}` language block is an
+/// error, like with the `--posix` flag.
+///
+/// Without the `shebang-block` feature the syntax is always strict.
+pub fn strict(strict: bool) {
+    STRICT.with(|s| s.set(strict));
+}
+
+pub(crate) fn is_strict() -> bool {
+    cfg!(not(feature = "shebang-block")) || STRICT.with(Cell::get)
 }
 
 /// Every token in the langauge, these are the terminals of the grammar.
@@ -82,12 +108,33 @@ pub struct Lexer<'input> {
     /// of the input, allows for EOF detection, amongst other things.
     lookahead: Option<(usize, char, usize)>,
 
+    /// True when the next word would be the first of a command, which is
+    /// the only place `!` is reserved, otherwise it's a word like `!=`.
+    command_start: bool,
+
+    /// The tokens of a substituted alias, which are returned before lexing
+    /// any more of the input.
+    pending: VecDeque<Span<Token<'input>, Error>>,
+
+    /// The aliases being substituted, which are never substituted again
+    /// inside themselves.
+    aliasing: Vec<String>,
+
+    /// True after an alias whose value ends in a blank, when the next word
+    /// may be an alias too.
+    alias_next: bool,
+
     #[cfg(feature = "shebang-block")]
     /// A boolean indicating we're currently lexing inside a shebang block,
     /// and should therefor output TEXT.
     //
     // TODO: Generalize over this abstraction and the one needed for HERE docs.
     in_shebang: bool,
+
+    #[cfg(feature = "shebang-block")]
+    /// The delimiter ending the text of the current block, given as
+    /// `<<WORD` after its interpreter, instead of a balanced `}`.
+    fence: Option<&'input str>,
 }
 
 impl<'input> Lexer<'input> {
@@ -100,8 +147,14 @@ impl<'input> Lexer<'input> {
             input,
             chars,
             lookahead,
+            command_start: true,
+            pending: VecDeque::new(),
+            aliasing: vec![],
+            alias_next: false,
             #[cfg(feature = "shebang-block")]
             in_shebang: false,
+            #[cfg(feature = "shebang-block")]
+            fence: None,
         }
     }
 }
@@ -110,12 +163,20 @@ impl<'input> Iterator for Lexer<'input> {
     type Item = Span<Token<'input>, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(tok) = self.pending.pop_front() {
+            if let Ok((_, ref t, _)) = tok {
+                self.command_start = starts_command(t);
+            }
+            debug!("emit<alias>: {:?}", tok);
+            return Some(tok);
+        }
+
         #[cfg(feature = "shebang-block")]
         {
             // If we're inside a shebang, parse a full TEXT block.
             if self.in_shebang {
-                let tok = if let Some((start, _, end)) = self.lookahead {
-                    Some(self.text(start, end))
+                let tok = if let Some((start, _, _)) = self.lookahead {
+                    Some(self.text(start, start))
                 } else {
                     None
                 };
@@ -129,16 +190,24 @@ impl<'input> Iterator for Lexer<'input> {
         while let Some((s, c, e)) = self.advance() {
             let tok = match c {
                 '\n' => Some(Ok((s, Token::Linefeed, e))),
+                // Line continuations are removed entirely.
+                '\\' if self.lookahead.map_or(false, |(_, c, _)| c == '\n') => {
+                    self.advance();
+                    continue;
+                },
                 ';'  => Some(Ok((s, Token::Semi, e))),
                 ')'  => Some(Ok((s, Token::RParen, e))),
                 '('  => Some(Ok((s, Token::LParen, e))),
-                '`'  => Some(Ok((s, Token::Backtick, e))),
-                '!'  => Some(Ok((s, Token::Bang, e))),
-                '$'  => Some(Ok((s, Token::Dollar, e))),
-                '='  => Some(Ok((s, Token::Equals, e))),
-                '\\' => Some(Ok((s, Token::Backslash, e))),
-                '\'' => Some(self.single_quote(s, e)),
-                '"'  => Some(self.double_quote(s, e)),
+                '!' if self.command_start && self.lookahead
+                    .map_or(true, |(_, c, _)| !is_word_continue(c)) =>
+                {
+                    Some(Ok((s, Token::Bang, e)))
+                },
+                '#'  => {
+                    // Comments run until the end of the line.
+                    self.take_until(s, e, |c| c == '\n');
+                    continue;
+                },
                 '>'  => {
                     match self.lookahead {
                         Some((_, '>', e)) => {
@@ -200,6 +269,19 @@ impl<'input> Iterator for Lexer<'input> {
                 c if c.is_whitespace() => continue,
                 c => return Some(Err(Error::UnrecognizedChar(s, c, e))),
             };
+            let alias = self.command_start || self.alias_next;
+            self.alias_next = false;
+            if let (true, Some(Ok((s, Token::Word(word), e)))) = (alias, &tok) {
+                if let Some(value) = alias::get(word) {
+                    if !self.aliasing.iter().any(|a| a == word) {
+                        self.substitute(word, value, *s, *e);
+                        return self.next();
+                    }
+                }
+            }
+            if let Some(Ok((_, ref t, _))) = tok {
+                self.command_start = starts_command(t);
+            }
             debug!("emit<end>: {:?}", tok);
             return tok;
         }
@@ -212,6 +294,32 @@ impl<'input> Iterator for Lexer<'input> {
 }
 
 impl<'input> Lexer<'input> {
+    // Lex the value of the alias `name` in place of the word at `start` to
+    // `end`. Every token of the value is given the word's location.
+    fn substitute(&mut self, name: &str, value: &'static str,
+                  start: usize, end: usize)
+    {
+        let mut lexer = Lexer::new(value);
+        lexer.aliasing = self.aliasing.clone();
+        lexer.aliasing.push(name.into());
+        for tok in &mut lexer {
+            self.pending.push_back(match tok {
+                Ok((_, t, _)) => Ok((start, t, end)),
+                Err(Error::UnrecognizedChar(_, c, _)) => {
+                    Err(Error::UnrecognizedChar(start, c, end))
+                },
+                Err(Error::Unterminated(..)) => {
+                    Err(Error::Unterminated(start, end))
+                },
+                Err(Error::Block(..)) => Err(Error::Block(start, end)),
+            });
+        }
+        // A trailing blank makes the next word a possible alias, even when
+        // it's an argument, like `alias sudo='sudo '`.
+        let blank = value.ends_with(|c| c == ' ' || c == '\t');
+        self.alias_next = lexer.alias_next || blank;
+    }
+
     fn advance(&mut self) -> Option<(usize, char, usize)> {
         match self.lookahead {
             Some((s, c, e)) => {
@@ -248,31 +356,112 @@ impl<'input> Lexer<'input> {
         self.take_until(start, end, |c| !keep_going(c))
     }
 
-    fn single_quote(&mut self, start: usize, end: usize)
-        -> Result<(usize, Token<'input>, usize), Error>
-    {
-        // TODO: This quitely stops at EOF.
+    // Consume the rest of a quote, started by the `'` at `start`.
+    fn single_quote(&mut self, start: usize, end: usize) -> Result<usize, Error> {
         let (_, end) = self.take_while(start, end, |c| c != '\'');
-        self.advance();  // Consume the ending single quote.
-        Ok((start, Token::Word(&self.input[start+1..end]), end))
+        self.advance().map(|(_, _, e)| e).ok_or(Error::Unterminated(start, end))
     }
 
-    // TODO: Escapes
-    // TODO: Should we expand $ variables here?
-    fn double_quote(&mut self, start: usize, end: usize)
-        -> Result<(usize, Token<'input>, usize), Error>
+    // Consume the rest of a quote, started by the `"` at `start`. Escapes
+    // and expansions are kept intact, they are handled during expansion.
+    fn double_quote(&mut self, start: usize, mut end: usize) -> Result<usize, Error> {
+        while let Some((s, c, e)) = self.advance() {
+            end = match c {
+                '"'  => return Ok(e),
+                '\\' => self.advance().map_or(e, |(_, _, e)| e),
+                '$'  => self.dollar(s, e)?,
+                '`'  => self.backtick(s, e)?,
+                _    => e,
+            };
+        }
+        Err(Error::Unterminated(start, end))
+    }
+
+    // Consume the rest of an expansion, started by the `$` at `start`.
+    fn dollar(&mut self, start: usize, end: usize) -> Result<usize, Error> {
+        match self.lookahead {
+            Some((_, '(', _)) => self.balanced(start, '(', ')'),
+            Some((_, '{', _)) => self.balanced(start, '{', '}'),
+            Some((_, c, e)) if is_special_parameter(c) => {
+                self.advance();
+                Ok(e)
+            },
+            Some((_, c, _)) if c == '_' || c.is_ascii_alphabetic() => {
+                let (_, end) = self.take_while(end, end, |c| {
+                    c == '_' || c.is_ascii_alphanumeric()
+                });
+                Ok(end)
+            },
+            _ => Ok(end),
+        }
+    }
+
+    // Consume the rest of a command substitution, started by the '`' at
+    // `start`.
+    fn backtick(&mut self, start: usize, mut end: usize) -> Result<usize, Error> {
+        while let Some((_, c, e)) = self.advance() {
+            end = match c {
+                '`'  => return Ok(e),
+                '\\' => self.advance().map_or(e, |(_, _, e)| e),
+                _    => e,
+            };
+        }
+        Err(Error::Unterminated(start, end))
+    }
+
+    // Consume everything up to and including the `close` matching the
+    // `open` in the lookahead, skipping over quotes.
+    fn balanced(&mut self, start: usize, open: char, close: char)
+        -> Result<usize, Error>
     {
-        // TODO: This quitely stops at EOF.
-        let (_, end) = self.take_while(start, end, |c| c != '"');
-        self.advance();  // Consume the ending double quote.
-        Ok((start, Token::Word(&self.input[start+1..end]), end))
+        let mut depth = 0;
+        let mut end = start;
+        while let Some((s, c, e)) = self.advance() {
+            end = match c {
+                '\''  => self.single_quote(s, e)?,
+                '"'   => self.double_quote(s, e)?,
+                '\\'  => self.advance().map_or(e, |(_, _, e)| e),
+                c if c == open => { depth += 1; e },
+                c if c == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(e);
+                    }
+                    e
+                },
+                _ => e,
+            };
+        }
+        Err(Error::Unterminated(start, end))
+    }
+
+    // Consume a single part of a word, started by the character at `start`.
+    fn word_part(&mut self, start: usize, end: usize) -> Result<usize, Error> {
+        match &self.input[start..end] {
+            "'"  => self.single_quote(start, end),
+            "\"" => self.double_quote(start, end),
+            "\\" => Ok(self.advance().map_or(end, |(_, _, e)| e)),
+            "$"  => self.dollar(start, end),
+            "`"  => self.backtick(start, end),
+            _    => Ok(end),
+        }
     }
 
+    // Words are kept exactly as written, including any quotes, escapes or
+    // expansions. Reserved words are only recognized when unquoted.
     fn word(&mut self, start: usize, end: usize)
         -> Result<(usize, Token<'input>, usize), Error>
     {
-        let (word, end) = self.take_while(start, end, is_word_continue);
-        let tok = match word {
+        let mut end = self.word_part(start, end)?;
+        while let Some((s, c, e)) = self.lookahead {
+            if !is_word_continue(c) {
+                break;
+            }
+            self.advance();
+            end = self.word_part(s, e)?;
+        }
+
+        let tok = match &self.input[start..end] {
             "if"    => Token::If,
             "then"  => Token::Then,
             "else"  => Token::Else,
@@ -306,17 +495,24 @@ impl<'input> Lexer<'input> {
     fn block(&mut self, start: usize, end: usize)
         -> Result<(usize, Token<'input>, usize), Error>
     {
+        if let Some((_, '#', e)) = self.lookahead {
+            if is_strict() {
+                return Err(Error::Block(start, e));
+            }
+        }
+
         #[cfg(feature = "shebang-block")]
         {
             if let Some((_, '#', s)) = self.lookahead {
                 self.advance();  // Consume the '#'.
                 if let Some((_, '!', s)) = self.lookahead {
-                    let (_, end) = self.take_until(s, end, |c| c == ';');
-                    self.advance();  // Consume the ';' delimeter.
+                    let (_, end) = self.take_until(s, end, |c| c == ';' || c == '\n');
+                    self.advance();  // Consume the ';' or '\n' delimeter.
                     self.take_while(end, end, |c| c.is_whitespace());
                     self.in_shebang = true;
 
-                    let tok = Token::Shebang(&self.input[s..end]);
+                    let header = self.fence(&self.input[s..end]);
+                    let tok = Token::Shebang(header);
                     return Ok((start, tok, end));
                 } else {
                     let (_, end) = self.take_until(s, s, char::is_whitespace);
@@ -324,6 +520,19 @@ impl<'input> Lexer<'input> {
                     let tok = Token::HashLang(&self.input[s..end]);
                     self.in_shebang = true;
 
+                    // The fence is the first word after the language, when
+                    // it's a `<<WORD`. Otherwise the word is part of the text.
+                    if let Some((f, '<', _)) = self.lookahead {
+                        let rest = &self.input[f..];
+                        let word = &rest[..rest.find(char::is_whitespace)
+                                                .unwrap_or_else(|| rest.len())];
+                        self.fence(word);
+                        if self.fence.is_some() {
+                            self.take_until(f, f, char::is_whitespace);
+                            self.advance();
+                        }
+                    }
+
                     return Ok((start, tok, end));
                 }
             }
@@ -332,13 +541,64 @@ impl<'input> Lexer<'input> {
         Ok((start, Token::LBrace, end))
     }
 
+    // Remove a trailing `<<WORD` from the header of a block, which is the
+    // fence ending its text.
+    #[cfg(feature = "shebang-block")]
+    fn fence(&mut self, header: &'input str) -> &'input str {
+        let header = header.trim_end();
+        let i = header.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        match &header[i..] {
+            word if word.len() > 2 && word.starts_with("<<") => {
+                self.fence = Some(&word[2..]);
+                header[..i].trim_end()
+            },
+            _ => header,
+        }
+    }
+
+    // The text of a block ends at the first `}` which isn't balanced by a
+    // `{` before it, or with a fence, at the fence followed by a `}`.
     #[cfg(feature = "shebang-block")]
     fn text(&mut self, start: usize, end: usize)
         -> Result<(usize, Token<'input>, usize), Error>
     {
-        // TODO: Count matching braces in TEXT.
-        let (_, end) = self.take_until(start, end, |d| d == '}');
         self.in_shebang = false;
+        let end = match self.fence.take() {
+            Some(fence) => {
+                let rest = &self.input[start..];
+                let found = rest.match_indices(fence).find(|(i, _)| {
+                    rest[i + fence.len()..]
+                        .trim_start_matches(|c| c == ' ' || c == '\t')
+                        .starts_with('}')
+                });
+                let (end, brace) = match found {
+                    Some((i, _)) => {
+                        let after = &rest[i + fence.len()..];
+                        let blanks = after.find('}').unwrap_or(0);
+                        (start + i, start + i + fence.len() + blanks)
+                    },
+                    None => (self.input.len(), self.input.len()),
+                };
+                // Skip the fence, leaving the `}`.
+                while self.lookahead.map_or(false, |(s, _, _)| s < brace) {
+                    self.advance();
+                }
+                end
+            },
+            None => {
+                let mut depth = 0;
+                let (_, end) = self.take_until(start, end, |c| {
+                    match c {
+                        '{' => depth += 1,
+                        '}' if depth == 0 => return true,
+                        '}' => depth -= 1,
+                        _ => {},
+                    }
+                    false
+                });
+                end
+            },
+        };
         Ok((start, Token::Text(&self.input[start..end]), end))
     }
 }
@@ -358,8 +618,7 @@ fn is_word_continue(ch: char) -> bool {
     match ch {
         // List of syntax from above.
         // TODO: Make this list generated.
-        ';' | ')' | '(' | '`' | '!' |
-        '$' | '=' | '\\' | '\'' | '"' |
+        ';' | ')' | '(' |
         '>' | '<' | '&' | '|' | '{' | '}' |
         '*' => false,
 
@@ -367,6 +626,34 @@ fn is_word_continue(ch: char) -> bool {
     }
 }
 
+/// Returns true for the reserved words of the language, which are never
+/// the names of commands.
+pub fn is_reserved(word: &str) -> bool {
+    match word {
+        "!" | "{" | "}" | "if" | "then" | "else" | "elif" | "fi" | "do" |
+        "done" | "case" | "esac" | "while" | "until" | "for" => true,
+        _ => false,
+    }
+}
+
+// Returns true if the token is followed by the first word of a command.
+fn starts_command(token: &Token) -> bool {
+    match token {
+        Token::Linefeed | Token::Semi | Token::Amper | Token::And |
+        Token::Or | Token::Pipe | Token::LParen | Token::LBrace |
+        Token::Bang | Token::If | Token::Then | Token::Else | Token::Elif |
+        Token::Do | Token::While | Token::Until => true,
+        _ => false,
+    }
+}
+
+fn is_special_parameter(ch: char) -> bool {
+    match ch {
+        '@' | '*' | '#' | '?' | '-' | '$' | '!' | '0'..='9' => true,
+        _ => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -384,11 +671,24 @@ mod tests {
                         Some(Err(Error::UnrecognizedChar(_, '*', _))));
     }
 
+    #[test]
+    fn unterminated() {
+        for text in &["'a", "\"a", "\"$(a\"", "$(a", "${a", "`a", "a'b"] {
+            assert_matches!(Lexer::new(text).last(),
+                            Some(Err(Error::Unterminated(_, _))));
+        }
+        assert_matches!(Lexer::new("'a\nb' $(a\n)").last(),
+                        Some(Ok((_, Token::Word("$(a\n)"), _))));
+    }
+
     #[test]
     fn linefeed() {
         let mut lexer = Lexer::new("\n");
         assert_matches!(lexer.next(),
                         Some(Ok((_, Token::Linefeed, _))));
+        let mut lexer = Lexer::new("\\\n a");
+        assert_matches!(lexer.next(),
+                        Some(Ok((_, Token::Word("a"), _))));
     }
 
     #[test]
@@ -475,6 +775,91 @@ mod tests {
                         Some(Ok((_, Token::Word("-🧪💀"), _))));
     }
 
+    #[test]
+    fn bang() {
+        let mut lexer = Lexer::new("! [ ! a != b ]; !x");
+        assert_matches!(lexer.next(),
+                        Some(Ok((_, Token::Bang, _))));
+        assert_matches!(lexer.next(),
+                        Some(Ok((_, Token::Word("["), _))));
+        assert_matches!(lexer.next(),
+                        Some(Ok((_, Token::Word("!"), _))));
+        assert_matches!(lexer.next(),
+                        Some(Ok((_, Token::Word("a"), _))));
+        assert_matches!(lexer.next(),
+                        Some(Ok((_, Token::Word("!="), _))));
+        assert_matches!(lexer.next(),
+                        Some(Ok((_, Token::Word("b"), _))));
+        assert_matches!(lexer.next(),
+                        Some(Ok((_, Token::Word("]"), _))));
+        assert_matches!(lexer.next(),
+                        Some(Ok((_, Token::Semi, _))));
+        assert_matches!(lexer.next(),
+                        Some(Ok((_, Token::Word("!x"), _))));
+    }
+
+    #[test]
+    fn aliases() {
+        alias::set("ll", "ls -l");
+        alias::set("loop", "loop again");
+        alias::set("s", "sudo ");
+        let words = |text| Lexer::new(text).map(|t| t.unwrap().1).collect::<Vec<_>>();
+        assert_eq!(vec![Token::Word("ls"), Token::Word("-l"), Token::Semi,
+                        Token::Word("echo"), Token::Word("ll")],
+                   words("ll; echo ll"));
+        assert_eq!(vec![Token::Word("loop"), Token::Word("again")],
+                   words("loop"));
+        assert_eq!(vec![Token::Word("sudo"), Token::Word("ls"),
+                        Token::Word("-l"), Token::Word("ll")],
+                   words("s ll ll"));
+        assert_eq!(vec![Token::Word("'ll'")], words("'ll'"));
+        alias::clear();
+    }
+
+    #[test]
+    #[cfg(feature = "shebang-block")]
+    fn blocks() {
+        fn tokens(text: &str) -> Vec<Token> {
+            Lexer::new(text).map(|t| t.unwrap().1).collect()
+        }
+
+        assert_eq!(vec![Token::Shebang("ruby"), Token::Text("h = {a: {}}"),
+                        Token::RBrace],
+                   tokens("{#!ruby; h = {a: {}}}"));
+        assert_eq!(vec![Token::HashLang("python"), Token::Text("print({})"),
+                        Token::RBrace, Token::Semi],
+                   tokens("{#python print({})};"));
+        assert_eq!(vec![Token::Shebang("ruby a"), Token::Text("puts '}'\n"),
+                        Token::RBrace],
+                   tokens("{#!ruby a <<END\nputs '}'\nEND }"));
+        assert_eq!(vec![Token::HashLang("node"), Token::Text("'END'; "),
+                        Token::RBrace],
+                   tokens("{#node <<END 'END'; END}"));
+        assert_eq!(vec![Token::Shebang("/bin/sh"), Token::Text(""),
+                        Token::RBrace],
+                   tokens("{#!/bin/sh;}"));
+        assert_eq!(vec![Token::HashLang("python@a"), Token::Text("x = 1"),
+                        Token::RBrace],
+                   tokens("{#python@a x = 1}"));
+        assert_eq!(vec![Token::HashLang("html"), Token::Text("<div>hi</div>"),
+                        Token::RBrace],
+                   tokens("{#html <div>hi</div>}"));
+        assert_eq!(vec![Token::HashLang("sh"), Token::Text("<< x"),
+                        Token::RBrace],
+                   tokens("{#sh << x}"));
+    }
+
+    #[test]
+    #[cfg(feature = "shebang-block")]
+    fn strict_blocks() {
+        strict(true);
+        let mut lexer = Lexer::new("{#!/bin/sh; echo}");
+        assert_matches!(lexer.next(), Some(Err(Error::Block(0, 2))));
+        assert_matches!(Lexer::new("{ echo; }").next(),
+                        Some(Ok((_, Token::LBrace, _))));
+        strict(false);
+    }
+
     #[test]
     fn keywords() {
         let mut lexer = Lexer::new("if ls done");
@@ -0,0 +1,291 @@
+//! Persistent interpreter sessions, for `{#lang@name ...}` blocks.
+//!
+//! Each block of a session is run by the same interpreter, which is started
+//! by the first block and keeps running in the background, so its variables
+//! carry from one block to the next, and its startup is only paid once.
+//!
+//! ```sh
+//! {#python@calc x = 6 * 7}
+//! {#python@calc print(x)}
+//! ```
+//!
+//! The interpreter runs an adapter, a small REPL written in its own
+//! language, which reads blocks from its standard input and writes back what
+//! they printed, and their status, to a pipe whose descriptor is given as
+//! its last argument. Each block is sent as its length in bytes on a line,
+//! followed by its text, and each reply is the length of the output on a
+//! line, followed by the output, and the status on a line. The adapter exits
+//! once its input is closed, and the shell waits for it when it exits.
+//!
+//! Only what a block prints to standard output through its language is
+//! captured. Anything written to descriptor 1 directly, like the output of
+//! a subprocess, goes to the shell's standard output. Its standard error is
+//! the shell's, and it has no standard input. Adapters exist for Python,
+//! Ruby and Node, for any language registered with one of those
+//! interpreters.
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::CString,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::Path,
+};
+use nix::{
+    fcntl::{fcntl, FcntlArg, FdFlag},
+    sys::wait::{waitpid, WaitStatus},
+    unistd::{close, pipe, Pid},
+};
+use crate::{
+    job::{Fd, IO, Job},
+    program::{Result, Error},
+};
+use super::hashlang::Language;
+
+/// A REPL reading blocks from the shell, run by an interpreter with the
+/// `option` given the `code`.
+struct Adapter {
+    option: &'static str,
+    code: &'static str,
+}
+
+const PYTHON: Adapter = Adapter {
+    option: "-c",
+    code: r##"
+import io, os, sys, traceback
+requests, replies = sys.stdin.buffer, os.fdopen(int(sys.argv[1]), 'wb')
+stdout = sys.stdout
+sys.stdin = open(os.devnull)
+scope = {'__name__': '__main__'}
+while True:
+    size = requests.readline()
+    if not size:
+        break
+    text = requests.read(int(size)).decode()
+    sys.stdout, status = io.StringIO(), 0
+    try:
+        exec(compile(text, '<block>', 'exec'), scope)
+    except SystemExit as e:
+        status = e.code if isinstance(e.code, int) else int(e.code is not None)
+    except BaseException:
+        traceback.print_exc()
+        status = 1
+    output = sys.stdout.getvalue().encode()
+    sys.stdout = stdout
+    replies.write(b'%d\n' % len(output) + output + b'%d\n' % status)
+    replies.flush()
+"##,
+};
+
+const RUBY: Adapter = Adapter {
+    option: "-e",
+    code: r##"
+require 'stringio'
+requests, replies = $stdin.binmode, IO.new(ARGV[0].to_i, 'wb')
+stdout = $stdout
+$stdin = File.open(File::NULL)
+while (size = requests.gets)
+  text = requests.read(size.to_i).force_encoding('UTF-8')
+  $stdout, status = StringIO.new, 0
+  begin
+    TOPLEVEL_BINDING.eval(text, '<block>')
+  rescue SystemExit => e
+    status = e.status
+  rescue Exception => e
+    $stderr.puts e.full_message
+    status = 1
+  end
+  output = $stdout.string.b
+  $stdout = stdout
+  replies.write("#{output.bytesize}\n", output, "#{status}\n")
+  replies.flush
+end
+"##,
+};
+
+const NODE: Adapter = Adapter {
+    option: "-e",
+    code: r##"
+const fs = require('fs'), vm = require('vm');
+globalThis.require = require;
+const replies = parseInt(process.argv[1]);
+let pending = Buffer.alloc(0);
+function read(size) {
+  while (size === undefined ? !pending.includes(10) : pending.length < size) {
+    const chunk = Buffer.alloc(65536);
+    const n = fs.readSync(0, chunk, 0, chunk.length, null);
+    if (n === 0) return null;
+    pending = Buffer.concat([pending, chunk.subarray(0, n)]);
+  }
+  const end = size === undefined ? pending.indexOf(10) + 1 : size;
+  const data = pending.subarray(0, end);
+  pending = pending.subarray(end);
+  return data;
+}
+const write = process.stdout.write;
+for (let size; (size = read()) !== null;) {
+  const text = read(parseInt(size)).toString();
+  let output = '', status = 0;
+  process.stdout.write = (chunk) => { output += chunk; return true; };
+  try {
+    vm.runInThisContext(text, {filename: '<block>'});
+  } catch (e) {
+    console.error(e);
+    status = 1;
+  }
+  process.stdout.write = write;
+  const data = Buffer.from(output);
+  fs.writeSync(replies, `${data.length}\n`);
+  fs.writeSync(replies, data);
+  fs.writeSync(replies, `${status}\n`);
+}
+"##,
+};
+
+// The adapter for an interpreter, by its name without a version, so
+// `python3` is `python`.
+fn adapter(interpreter: &str) -> Option<&'static Adapter> {
+    let name = Path::new(interpreter).file_name()?.to_string_lossy();
+    match name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+        "python" => Some(&PYTHON),
+        "ruby" => Some(&RUBY),
+        "node" | "nodejs" => Some(&NODE),
+        _ => None,
+    }
+}
+
+/// A running interpreter, and the pipes to and from its adapter.
+struct Session {
+    job: Job,
+    requests: Fd,
+    replies: BufReader<Fd>,
+}
+
+impl Session {
+    fn start(interpreter: &[String], adapter: &Adapter) -> Result<Self> {
+        // The shell's ends of the pipes are closed on `exec`, so the adapter
+        // (and anything else the shell runs) won't keep them open. The end
+        // for replies is kept open by its number, leaving the adapter's
+        // standard output free for the blocks.
+        let (requests, input) = pipe()?;
+        let (output, replies) = pipe()?;
+        for fd in &[input, output] {
+            fcntl(*fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        }
+        let fd = replies.to_string();
+        let argv = interpreter.iter().map(String::as_str)
+            .chain(vec![adapter.option, adapter.code, &fd])
+            .map(|w| CString::new(w).expect("error in interpreter"))
+            .collect();
+        let mut job = Job::new(argv);
+        let status = job.fork(IO([requests, 1, 2]));
+        close(requests)?;
+        close(replies)?;
+        status?;
+
+        Ok(Session {
+            job,
+            requests: Fd(input),
+            replies: BufReader::new(Fd(output)),
+        })
+    }
+
+    // Send the text of a block, and read back its output and status.
+    fn send(&mut self, text: &str) -> io::Result<(Vec<u8>, i32)> {
+        let mut request = format!("{}\n", text.len()).into_bytes();
+        request.extend_from_slice(text.as_bytes());
+        self.requests.write_all(&request)?;
+
+        let size = self.line()?;
+        let mut output = vec![0; size as usize];
+        self.replies.read_exact(&mut output)?;
+        let status = self.line()?;
+        Ok((output, status))
+    }
+
+    fn line(&mut self) -> io::Result<i32> {
+        let mut line = String::new();
+        if self.replies.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        line.trim().parse().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, "invalid reply")
+        })
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        close(self.requests.0).ok();
+        close(self.replies.get_ref().0).ok();
+        if let Some(pid) = self.job.pid() {
+            waitpid(pid, None).ok();
+        }
+    }
+}
+
+thread_local! {
+    static SESSIONS: RefCell<HashMap<String, Session>> = RefCell::new(HashMap::new());
+}
+
+/// End every session, waiting for their interpreters to exit.
+///
+/// Sessions end when they're dropped, which doesn't happen when the shell
+/// exits with `process::exit`, so this is called before it does.
+pub fn end() {
+    SESSIONS.with(|sessions| sessions.borrow_mut().clear());
+}
+
+/// Run the text of a block in the named session, starting it with the
+/// language's interpreter if it isn't running.
+///
+/// A session whose interpreter has exited, for example by calling `exit`,
+/// is started again by the next block.
+pub fn run(name: &str, language: &Language, text: &str, io: IO) -> Result<WaitStatus> {
+    SESSIONS.with(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+        if !sessions.contains_key(name) {
+            // The interpreter's own options come before the `{}` of the
+            // block's file, anything after it is for the file.
+            let interpreter: Vec<String> = language.argv.iter()
+                .take_while(|word| !word.contains("{}"))
+                .cloned()
+                .collect();
+            let command = interpreter.first().map_or("", String::as_str);
+            let adapter = match adapter(command) {
+                Some(adapter) => adapter,
+                None => {
+                    eprintln!("oursh: {}: no sessions for {}", name, command);
+                    return Ok(WaitStatus::Exited(Pid::this(), 127));
+                },
+            };
+            sessions.insert(name.into(), Session::start(&interpreter, adapter)?);
+        }
+
+        let reply = sessions.get_mut(name).map(|s| s.send(text));
+        match reply {
+            Some(Ok((output, status))) => {
+                io.stdout().write_all(&output).map_err(Error::Read)?;
+                Ok(WaitStatus::Exited(Pid::this(), status))
+            },
+            _ => {
+                sessions.remove(name);
+                eprintln!("oursh: {}: session ended", name);
+                Ok(WaitStatus::Exited(Pid::this(), 1))
+            },
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adapters() {
+        assert!(adapter("python").is_some());
+        assert!(adapter("/usr/bin/python3.8").is_some());
+        assert!(adapter("nodejs").is_some());
+        assert!(adapter("awk").is_none());
+        assert!(adapter("").is_none());
+    }
+}
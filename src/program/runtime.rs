@@ -0,0 +1,306 @@
+//! Shell state which lives across the commands of a program.
+//!
+//! Where a `Job` is the state of a single command, the runtime is the state
+//! of the shell itself: its options, variables and positional parameters.
+//! Every command of every program run by a shell shares one `Runtime`.
+
+use std::{
+    env,
+    fs,
+    io,
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    os::unix::fs::MetadataExt,
+    path::PathBuf,
+    rc::Rc,
+    time::Instant,
+};
+use nix::unistd::{getpid, Pid};
+use super::Run;
+
+/// A shared handle to the state of the running shell.
+pub type Runtime = Rc<RefCell<State>>;
+
+/// The state of the running shell.
+#[derive(Debug)]
+pub struct State {
+    /// The options set with `set` or on the command line.
+    pub options: Options,
+    /// The name of the shell or script, `$0`.
+    pub name: String,
+    /// The positional parameters, `$1` through `$n`.
+    pub args: Vec<String>,
+    /// The exit status of the last command, `$?`.
+    pub status: i32,
+    /// The process ID of the shell, `$$`.
+    pub pid: Pid,
+    /// The process ID of the last background command, `$!`.
+    pub background: Option<Pid>,
+    /// True when the shell is reading commands from a user.
+    pub interactive: bool,
+    /// How many enclosing commands are ignoring `errexit` right now, for
+    /// example the left side of `&&`.
+    pub(crate) conditional: usize,
+    /// The commands written for `xtrace` and when they started, kept until
+    /// they finish for `xtracetime`. Commands run by other commands, like
+    /// `eval` or a function, are traced above the commands running them.
+    pub(crate) traced: Vec<(String, Instant)>,
+    /// The status of the last command substitution, which is the status of
+    /// a command without any words.
+    pub(crate) substitution: Option<i32>,
+    /// The directory stack of `pushd` and `popd`, from its top, without the
+    /// current directory.
+    pub(crate) directories: Vec<PathBuf>,
+    /// The `$OPTIND` last set by `getopts`, and the position of the next
+    /// flag in that argument.
+    pub(crate) getopts: (usize, usize),
+    /// The functions defined by programs, by name.
+    pub(crate) functions: HashMap<String, Function>,
+    // Variables which aren't exported, exported variables are stored in the
+    // environment of the shell process itself.
+    variables: HashMap<String, String>,
+}
+
+impl State {
+    /// Create the state for a new shell, named `$0`.
+    pub fn new(name: &str) -> Self {
+        State {
+            options: Options::default(),
+            name: name.into(),
+            args: vec![],
+            status: 0,
+            pid: getpid(),
+            background: None,
+            interactive: false,
+            conditional: 0,
+            traced: vec![],
+            substitution: None,
+            directories: vec![],
+            getopts: (1, 1),
+            functions: HashMap::new(),
+            variables: vec![("OPTIND".into(), "1".into())].into_iter().collect(),
+        }
+    }
+
+    /// Create a new shared runtime.
+    pub fn shared(self) -> Runtime {
+        Rc::new(RefCell::new(self))
+    }
+
+    /// Return the value of the named variable, exported or not.
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.variables.get(name).cloned().or_else(|| env::var(name).ok())
+    }
+
+    /// Assign a value to the named variable.
+    ///
+    /// Variables which are already exported stay exported, and with the
+    /// `allexport` option every assigned variable is exported.
+    pub fn set(&mut self, name: &str, value: &str) {
+        if self.options.allexport || env::var_os(name).is_some() {
+            self.variables.remove(name);
+            env::set_var(name, value);
+        } else {
+            self.variables.insert(name.into(), value.into());
+        }
+    }
+
+    /// Remove the named variable, exported or not.
+    pub fn unset(&mut self, name: &str) {
+        self.variables.remove(name);
+        env::remove_var(name);
+    }
+
+    /// Export the named variable to the environment of future commands.
+    pub fn export(&mut self, name: &str) {
+        if let Some(value) = self.variables.remove(name) {
+            env::set_var(name, value);
+        }
+    }
+
+    /// The current directory as named by `$PWD`, which may go through
+    /// symbolic links.
+    ///
+    /// When `$PWD` isn't an absolute path to the current directory, without
+    /// any `.` or `..` components, the physical directory is used instead.
+    pub fn pwd(&self) -> io::Result<PathBuf> {
+        let physical = env::current_dir()?;
+        if let Some(pwd) = self.get("PWD") {
+            let normal = pwd.starts_with('/') &&
+                         !pwd.split('/').any(|c| c == "." || c == "..");
+            let same = match (fs::metadata(&pwd), fs::metadata(&physical)) {
+                (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+                _ => false,
+            };
+            if normal && same {
+                return Ok(PathBuf::from(pwd));
+            }
+        }
+        Ok(physical)
+    }
+
+    /// Return every variable, exported or not, sorted by name.
+    pub fn variables(&self) -> Vec<(String, String)> {
+        let mut variables: Vec<(String, String)> = env::vars()
+            .filter(|(name, _)| !self.variables.contains_key(name))
+            .chain(self.variables.clone().into_iter())
+            .collect();
+        variables.sort();
+        variables
+    }
+}
+
+/// A function defined by a program, which is called like any other command.
+#[derive(Clone)]
+pub struct Function {
+    /// The names of the parameters, which are set to the arguments of each
+    /// call.
+    pub params: Vec<String>,
+    /// The command run by each call, in the language it was defined in.
+    pub body: Rc<dyn Run>,
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Function").field("params", &self.params).finish()
+    }
+}
+
+/// Shell options, which change the behavior of the shell.
+///
+/// Each option has a long name for `set -o name`, and most have a single
+/// letter flag for `set -x`, see the table in [`Options::FLAGS`].
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Options {
+    /// Export every variable assigned to.
+    pub allexport: bool,
+    /// Exit the shell when a command fails.
+    pub errexit: bool,
+    /// Expand the parameters in the text of language blocks, like a
+    /// here-document, before it's run. This is an oursh extension.
+    pub interpolate: bool,
+    /// Don't overwrite existing files with the `>` redirection.
+    pub noclobber: bool,
+    /// Disable pathname expansion.
+    ///
+    /// Oursh doesn't perform pathname expansion yet, so this is always
+    /// effectively on.
+    pub noglob: bool,
+    /// Read commands without running them, ignored by interactive shells.
+    pub noexec: bool,
+    /// Treat expansion of unset parameters as an error.
+    pub nounset: bool,
+    /// Return the status of the last failing command of a pipeline.
+    pub pipefail: bool,
+    /// Write input to STDERR as it's read.
+    pub verbose: bool,
+    /// Write each command to STDERR before it's run.
+    pub xtrace: bool,
+    /// Write how long each command traced by `xtrace` took, and its exit
+    /// status, once it finishes. This is an oursh extension.
+    pub xtracetime: bool,
+}
+
+impl Options {
+    /// The names of every option, with their flag if they have one.
+    pub const FLAGS: &'static [(&'static str, Option<char>)] = &[
+        ("allexport", Some('a')),
+        ("errexit",   Some('e')),
+        ("interpolate", None),
+        ("noclobber", Some('C')),
+        ("noglob",    Some('f')),
+        ("noexec",    Some('n')),
+        ("nounset",   Some('u')),
+        ("pipefail",  None),
+        ("verbose",   Some('v')),
+        ("xtrace",    Some('x')),
+        ("xtracetime", None),
+    ];
+
+    /// Return the option with the given long name.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "allexport" => Some(&mut self.allexport),
+            "errexit"   => Some(&mut self.errexit),
+            "interpolate" => Some(&mut self.interpolate),
+            "noclobber" => Some(&mut self.noclobber),
+            "noglob"    => Some(&mut self.noglob),
+            "noexec"    => Some(&mut self.noexec),
+            "nounset"   => Some(&mut self.nounset),
+            "pipefail"  => Some(&mut self.pipefail),
+            "verbose"   => Some(&mut self.verbose),
+            "xtrace"    => Some(&mut self.xtrace),
+            "xtracetime" => Some(&mut self.xtracetime),
+            _ => None,
+        }
+    }
+
+    /// Return the value of the option with the given long name.
+    pub fn get(&self, name: &str) -> Option<bool> {
+        let mut options = *self;
+        options.get_mut(name).map(|o| *o)
+    }
+
+    /// Return the long name of the option with the given flag.
+    pub fn name(flag: char) -> Option<&'static str> {
+        Self::FLAGS.iter()
+            .find(|(_, f)| *f == Some(flag))
+            .map(|(name, _)| *name)
+    }
+
+    /// The flags of every option which is set, as reported by `$-`.
+    ///
+    /// ```
+    /// use oursh::program::runtime::Options;
+    ///
+    /// let mut options = Options::default();
+    /// options.errexit = true;
+    /// options.xtrace = true;
+    /// assert_eq!("ex", options.flags());
+    /// ```
+    pub fn flags(&self) -> String {
+        Self::FLAGS.iter().filter_map(|(name, flag)| {
+            match (self.get(name), flag) {
+                (Some(true), Some(flag)) => Some(*flag),
+                _ => None,
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variables() {
+        let mut state = State::new("oursh");
+        assert_eq!(None, state.get("OURSH_TEST_UNEXPORTED"));
+        state.set("OURSH_TEST_UNEXPORTED", "1");
+        assert_eq!(Some("1".into()), state.get("OURSH_TEST_UNEXPORTED"));
+        assert!(env::var("OURSH_TEST_UNEXPORTED").is_err());
+        state.export("OURSH_TEST_UNEXPORTED");
+        assert_eq!(Ok("1".into()), env::var("OURSH_TEST_UNEXPORTED"));
+    }
+
+    #[test]
+    fn allexport() {
+        let mut state = State::new("oursh");
+        state.options.allexport = true;
+        state.set("OURSH_TEST_ALLEXPORT", "1");
+        assert_eq!(Ok("1".into()), env::var("OURSH_TEST_ALLEXPORT"));
+    }
+
+    #[test]
+    fn options() {
+        let mut options = Options::default();
+        assert_eq!(Some("errexit"), Options::name('e'));
+        assert_eq!(None, Options::name('z'));
+        *options.get_mut("nounset").unwrap() = true;
+        assert!(options.nounset);
+        assert_eq!(Some(true), options.get("nounset"));
+        assert_eq!("u", options.flags());
+        assert!(options.get_mut("nonsense").is_none());
+    }
+}
@@ -4,7 +4,7 @@ use crate::program::Result;
 use super::prompt::Prompt;
 
 use {
-    std::process::exit,
+    crate::program::exit,
     termion::cursor::DetectCursorPos,
     termion::raw::RawTerminal,
 };
@@ -16,12 +16,8 @@
 //! assert_eq!("cargo", &text);
 //! ```
 
-use std::{
-    env,
-    fs,
-    cmp::Ordering::Equal,
-    os::unix::fs::PermissionsExt,
-};
+use std::cmp::Ordering::Equal;
+use crate::lookup;
 
 /// The result of a query for text completion.
 ///
@@ -105,47 +101,25 @@ pub fn complete(text: &str) -> Completion {
 ///     .contains(&"ruby".into()));
 /// ```
 pub fn executable_completions(text: &str) -> Completion {
-    match env::var_os("PATH") {
-        Some(paths) => {
-            let mut matches = vec![];
-            for dir in env::split_paths(&paths) {
-                if let Ok(executables) = fs::read_dir(dir) {
-                    let paths = executables.filter_map(|e| {
-                        match e { Ok(p) => Some(p.path()), _ => None }
-                    });
+    // The executables of each `$PATH` directory are remembered until it
+    // changes, so this doesn't read every directory each time.
+    let mut matches: Vec<String> = lookup::executables().into_iter()
+        .filter(|name| name.starts_with(text))
+        .collect();
 
-                    for path in paths {
-                        if let Some(filename) = path.file_name() {
-                            let filename = filename.to_string_lossy();
-                            if let Ok(metadata) = fs::metadata(&path) {
-                                if (metadata.permissions()
-                                            .mode() & 0o111 != 0)
-                                    && filename.starts_with(text)
-                                {
-                                    matches.push(filename.into());
-                                }
-                            }
-                        }
-                    }
+    match matches.len() {
+        0 => Completion::None,
+        1 => Completion::Complete(matches.remove(0)),
+        _ => {
+            matches.sort_by(|a, b| {
+                match a.len().cmp(&b.len()) {
+                    Equal => b.cmp(&a),
+                    o => o
                 }
-            }
-
-            match matches.len() {
-                0 => Completion::None,
-                1 => Completion::Complete(matches.remove(0)),
-                _ => {
-                    matches.sort_by(|a, b| {
-                        match a.len().cmp(&b.len()) {
-                            Equal => b.cmp(&a),
-                            o => o
-                        }
-                    });
-                    Completion::Partial(matches)
-                }
-            }
-
+            });
+            matches.dedup();
+            Completion::Partial(matches)
         }
-        None => panic!("PATH is undefined"),
     }
 }
 
@@ -1,3 +1,38 @@
+use std::{env, fs, path::PathBuf, process};
+
+/// A directory for the files written by a test, removed when it's dropped.
+///
+/// Each is named after its test and the process running it, so tests never
+/// share files, even when they run at the same time.
+#[allow(dead_code)]
+pub struct TempDir(PathBuf);
+
+#[allow(dead_code)]
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("oursh-{}-{}", name, process::id()));
+        fs::remove_dir_all(&path).ok();
+        fs::create_dir_all(&path).expect("error creating temporary directory");
+        TempDir(path.canonicalize().expect("error finding temporary directory"))
+    }
+
+    /// The path of the directory itself.
+    pub fn path(&self) -> String {
+        self.0.to_string_lossy().into_owned()
+    }
+
+    /// The path of a file in the directory.
+    pub fn join(&self, file: &str) -> String {
+        self.0.join(file).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
 #[macro_export]
 macro_rules! shell {
     ($executable:expr, $args:expr, $text:expr) => {{
@@ -28,8 +63,8 @@ macro_rules! shell {
         use std::process::{Command, Stdio};
 
         let child = Command::new($executable)
-            .arg($filename)
             .args($args)
+            .arg($filename)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
@@ -73,6 +108,16 @@ macro_rules! assert_oursh {
         let Output { status, .. } = oursh!($text);
         assert!(!status.success());
     }};
+    (! $text:expr, $stdout:expr) => {{
+        use std::process::Output;
+
+        let Output { status, stdout, stderr } = oursh!($text);
+        let stdout = String::from_utf8_lossy(&stdout);
+        let stderr = String::from_utf8_lossy(&stderr);
+        println!("stdout: {}\nstderr: {}", stdout, stderr);
+        assert!(!status.success());
+        assert_eq!($stdout, stdout);
+    }};
     ($text:expr) => {{
         use std::process::Output;
 
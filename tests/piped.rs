@@ -8,9 +8,35 @@ fn hello_world() {
 #[test]
 fn builtin_cd() {
     assert_oursh!("cd /; pwd", "/\n");
-    // assert_oursh!("cd; pwd", "$HOME\n");
+    assert_oursh!("cd / && echo $PWD", "/\n");
+    assert_oursh!("HOME=/tmp; cd; pwd", "/tmp\n");
     // assert_oursh!("cd ~; pwd", "$HOME\n");
-    // assert_oursh!("cd /; cd /home; cd -", "/\n");
+    assert_oursh!("cd /; cd /tmp; cd -; echo $OLDPWD", "/\n/tmp\n");
+    assert_oursh!(! "cd /does/not/exist");
+}
+
+#[test]
+fn builtin_cd_logical() {
+    let dir = common::TempDir::new("cd");
+    let (root, link) = (dir.path(), dir.join("link"));
+    assert_oursh!(format!("mkdir -p {0}/a/b; ln -s {0}/a/b {1}; cd {1}; pwd; cd ..; pwd",
+                          root, link),
+                  format!("{}\n{}\n", link, root));
+    assert_oursh!(format!("cd -P {}; echo $PWD; cd -L ..; pwd", link),
+                  format!("{0}/a/b\n{0}/a\n", root));
+    assert_oursh!(format!("CDPATH={}; cd a; cd ./b; pwd", root),
+                  format!("{0}/a\n{0}/a/b\n", root));
+}
+
+#[test]
+fn builtin_pushd_popd() {
+    assert_oursh!("cd /tmp; pushd /; pushd /usr; dirs -p", "/ /tmp\n/usr / /tmp\n/usr\n/\n/tmp\n");
+    assert_oursh!("cd /tmp; pushd /; pushd; popd; pwd", "/ /tmp\n/tmp /\n/\n/\n");
+    assert_oursh!("cd /tmp; pushd /; pushd /usr; pushd +2; popd +1; dirs -c; dirs",
+                  "/ /tmp\n/usr / /tmp\n/tmp /usr /\n/tmp /\n/tmp\n");
+    assert_oursh!(! "cd /tmp; popd");
+    assert_oursh!("cd /nowhere 2> /dev/null; popd 2> /dev/null; pushd +5 2> /dev/null; echo $?",
+                  "1\n", "");
 }
 
 #[test]
@@ -19,6 +45,30 @@ fn builtin_exit() {
     assert_oursh!(! "exit 1");
 }
 
+#[test]
+fn exit_status() {
+    assert_eq!(Some(0), oursh!("true").status.code());
+    assert_eq!(Some(1), oursh!("false").status.code());
+    assert_eq!(Some(0), oursh!("false; true").status.code());
+    assert_eq!(Some(1), oursh!("true; false").status.code());
+    assert_eq!(Some(3), oursh!("sh -c 'exit 3'").status.code());
+    assert_eq!(Some(127), oursh!("not-a-real-command").status.code());
+}
+
+#[test]
+fn error_status() {
+    // Syntax errors stop a non-interactive shell.
+    assert_eq!(Some(2), oursh!("echo 1; fi; echo 2").status.code());
+    // Redirection errors only fail the command.
+    let output = oursh!("cat < /does/not/exist; echo 1");
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("1\n", String::from_utf8_lossy(&output.stdout));
+    // Unless the command is a special builtin.
+    let output = oursh!(": < /does/not/exist; echo 1");
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!("", String::from_utf8_lossy(&output.stdout));
+}
+
 #[test]
 fn builtin_null() {
     assert_oursh!(":");
@@ -60,15 +110,14 @@ fn multiple_compound_command() {
 
 #[test]
 fn not_command() {
-    // TODO: Test status of the `oursh` command too.
-    // `assert_oursh!(! "! true");`
-    assert_oursh!("! true && echo 1", "");
+    assert_oursh!(! "! true");
+    assert_oursh!(! "! true && echo 1", "");
 }
 
 #[test]
 fn and_command() {
     assert_oursh!("true && echo 1", "1\n");
-    assert_oursh!("false && echo 1", "");
+    assert_oursh!(! "false && echo 1", "");
 }
 
 #[test]
@@ -85,6 +134,19 @@ fn cond_command() {
     assert_oursh!("if false; then echo 1; elif true; then echo 2; else echo 3; fi", "2\n");
 }
 
+#[test]
+fn loop_command() {
+    assert_oursh!("X=; while [ \"$X\" != aaa ]; do X=a$X; echo $X; done",
+                  "a\naa\naaa\n");
+    assert_oursh!("X=; until [ \"$X\" = aa ]; do X=a$X; done; echo $X", "aa\n");
+    assert_oursh!("while false; do echo 1; done");
+    assert_oursh!("if true\nthen\n\n  echo 1\nelse\n  echo 2\nfi\n{\n  echo 3\n}\n",
+                  "1\n3\n");
+    let dir = common::TempDir::new("loop");
+    assert_oursh!(format!("{{ echo 1; echo 2; }} > {0}; cat {0}", dir.join("file")),
+                  "1\n2\n");
+}
+
 #[test]
 fn subshell_command() {
     assert_oursh!("( true )");
@@ -103,16 +165,351 @@ fn chained_pipeline_command() {
     assert_oursh!("cat README.md | head | wc -l", "10\n");
 }
 
+#[test]
+fn compound_pipeline_command() {
+    assert_oursh!("echo x | { read a; echo $a; }", "x\n");
+    assert_oursh!("printf '1\\n2\\n' | while read x; do echo n$x; done | wc -l", "2\n");
+    assert_oursh!("echo 1 | if true; then cat; fi > /dev/null; echo $?", "0\n");
+    assert_oursh!("nosuch | cat; echo $?", "0\n");
+}
+
 #[test]
 fn assignment_command() {
     assert_oursh!("PI=3.1415 printenv PI", "3.1415\n");
     assert_oursh!("X=1 Y=2 printenv X Y", "1\n2\n");
-    assert_oursh!("X=1; printenv X", "\n");
+    // Unexported variables aren't in the environment of commands.
+    assert_oursh!(! "X=1; printenv X", "");
+}
+
+#[test]
+fn builtin_export() {
+    assert_oursh!("X=1; export X; printenv X", "1\n");
+    assert_oursh!("export X=1 Y=2; printenv X Y", "1\n2\n");
+    assert_oursh!("export X='a b'; export -p | grep '^export X='", "export X='a b'\n");
+    assert_oursh!(! "export 1X=1");
 }
 
 #[test]
 fn variable_command() {
     assert_oursh!("X=1; echo $X", "1\n");
+    assert_oursh!("set -- a b c; echo $# $2 \"$@\"", "3 b a b c\n");
+    assert_oursh!("set -- a b c; IFS=:; echo \"$*\"", "a:b:c\n");
+    assert_oursh!("set -- a b c; IFS=; echo \"$*\"", "abc\n");
+    assert_oursh!("X=abc.txt; echo ${X%.txt} ${#X} ${Y:-default}", "abc 7 default\n");
+}
+
+#[test]
+fn command_substitution() {
+    assert_oursh!("X=$(echo 1); echo \"[$X]\"", "[1]\n");
+    assert_oursh!("echo $(printf 'a\\nb\\n\\n')", "a b\n");
+    assert_oursh!("echo \"$(printf 'a\\nb\\n\\n')\"", "a\nb\n");
+    assert_oursh!("echo `echo a` $(echo $(echo b))", "a b\n");
+    assert_oursh!("X=1; echo $(echo $X)", "1\n");
+    assert_oursh!("X=$(false) || echo $?", "1\n");
+    assert_oursh!("echo \"$(printf 'a\\0b')\"", "ab\n");
+}
+
+#[test]
+fn builtin_shift() {
+    assert_oursh!("set -- a b c; shift; echo $# $@", "2 b c\n");
+    assert_oursh!("set -- a b c; shift 3; echo $#", "0\n");
+    assert_oursh!(! "set -- a b c; shift 4; echo 1", "");
+    assert_oursh!(! "shift x; echo 1", "");
+}
+
+#[test]
+fn command_string_args() {
+    let output = shell!("target/debug/oursh",
+                        &["--noprofile", "-c", "echo $0 $# $*", "name", "a", "b"],
+                        "");
+    assert_eq!("name 2 a b\n", String::from_utf8_lossy(&output.stdout));
+}
+
+#[test]
+fn builtin_dot() {
+    assert_oursh!(". ./scripts/lib.sh; echo $LIB $1", "lib 0 sourced\n");
+    assert_oursh!("set -- a; source ./scripts/lib.sh b c; echo $LIB $1", "lib 2 a\n");
+    assert_oursh!("PATH=./scripts:$PATH; . lib.sh; echo $LIB", "lib 0\n");
+    assert_oursh!(! ". not-a-real-file; echo 1", "");
+}
+
+#[test]
+fn profile() {
+    use std::fs;
+
+    let dir = common::TempDir::new("profile");
+    let profile = dir.join(".oursh_profile");
+    let home = format!("HOME={}", dir.path());
+    fs::write(&profile, "X=1\nfalse\n").unwrap();
+    let output = shell!("env", &[&home, "target/debug/oursh"], "echo $X $?");
+    assert_eq!("1 1\n", String::from_utf8_lossy(&output.stdout));
+    // Errors are reported like the errors of `.`, without stopping the shell.
+    fs::write(&profile, "echo 1\nfi\n").unwrap();
+    let output = shell!("env", &[&home, "target/debug/oursh"], "echo $?");
+    assert_eq!("2\n", String::from_utf8_lossy(&output.stdout));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("syntax error"));
+}
+
+#[test]
+fn builtin_eval() {
+    assert_oursh!("X=1; eval echo '$X'", "1\n");
+    assert_oursh!("eval 'Y=2;' 'echo $Y'", "2\n");
+    assert_oursh!("eval; echo $?", "0\n");
+    assert_oursh!(! "eval 'fi'; echo 1", "");
+}
+
+#[test]
+fn command_files() {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    let dir = common::TempDir::new("files");
+    fs::write(dir.join("script"), "echo script $1\n").unwrap();
+    fs::set_permissions(dir.join("script"), fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.join("text"), "echo text\n").unwrap();
+    // Scripts without a `#!` line are run by `/bin/sh`.
+    assert_oursh!(format!("PATH={}:$PATH; script a; {} b", dir.path(), dir.join("script")),
+                  "script a\nscript b\n");
+    // Files which can't be executed fail with 126, and missing ones with 127.
+    assert_oursh!(format!("PATH={}:$PATH; text; echo $?; {}; echo $?; not-a-command; echo $?",
+                          dir.path(), dir.join("text")),
+                  "126\n126\n127\n");
+    assert_eq!(Some(126), oursh!(format!("exec {}", dir.join("text"))).status.code());
+}
+
+#[test]
+fn builtin_exec() {
+    assert_oursh!("exec echo 1; echo 2", "1\n");
+    let dir = common::TempDir::new("exec");
+    let file = dir.join("file");
+    assert_oursh!(format!("exec > {}; echo 1; echo 2", file));
+    assert_oursh!(format!("cat {}", file), "1\n2\n");
+    assert_eq!(Some(127), oursh!("exec not-a-real-command; echo 1").status.code());
+}
+
+#[test]
+fn redirect_duplicate() {
+    assert_oursh!("echo 1 >&2", "", "1\n");
+    assert_oursh!("{ echo 1 >&2; } 2>&1", "1\n");
+    assert_oursh!("exec 2>&1; echo 1 1>&2", "1\n");
+    assert_oursh!(! "echo 1 >&-");
+    assert_oursh!(! "echo 1 >&5");
+    assert_oursh!(! "echo 1 3> /dev/null");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn redirect_closes_files() {
+    assert_oursh!("A=$(ls /proc/$$/fd); : > /dev/null; read X < /dev/null; \
+                   cat < /dev/null; B=$(ls /proc/$$/fd); test \"$A\" = \"$B\" && echo 1",
+                  "1\n");
+    assert_oursh!("A=$(ls /proc/$$/fd); exec 2> /dev/null; B=$(ls /proc/$$/fd); \
+                   test \"$A\" = \"$B\" && echo 1",
+                  "1\n");
+}
+
+#[test]
+fn builtin_test() {
+    assert_oursh!("test -f Cargo.toml && echo 1", "1\n");
+    assert_oursh!("[ ! -d Cargo.toml ] && echo 1", "1\n");
+    assert_oursh!("X=a; if [ \"$X\" != b -a 1 -lt 2 ]; then echo 1; fi", "1\n");
+    assert_oursh!("[ -z \"\" ]; echo $?; [ a = b ]; echo $?; [ 1 -eq x ]; echo $?",
+                  "0\n1\n2\n");
+    assert_oursh!("[ 1 -gt x ] 2> /dev/null; echo $?", "2\n", "");
+    assert_oursh!("[ 1 = 1; echo $?", "2\n");
+    assert_oursh!("! [ a = b ] && echo 1", "1\n");
+}
+
+#[test]
+fn builtin_echo() {
+    assert_oursh!("echo", "\n");
+    assert_oursh!("echo -n 1; echo 2", "12\n");
+    assert_oursh!("echo 'a\\tb\\0101'", "a\tbA\n");
+    assert_oursh!("echo 'a\\cb' c; echo d", "ad\n");
+    let dir = common::TempDir::new("echo");
+    assert_oursh!(format!("echo 1 > {0}; cat {0}", dir.join("file")), "1\n");
+}
+
+#[test]
+fn builtin_printf() {
+    assert_oursh!("printf '%s=%d\\n' a 1 b 2", "a=1\nb=2\n");
+    assert_oursh!("printf '%5.1f|%-3s|%x\\n' 3.14159 a 255", "  3.1|a  |ff\n");
+    assert_oursh!("printf '%b' 'a\\nb'", "a\nb");
+    assert_oursh!(! "printf '%d' x", "0");
+    assert_oursh!("printf '%d %y\n' x 2> /dev/null; echo $?", "0 1\n", "");
+}
+
+#[test]
+fn builtin_pwd() {
+    assert_oursh!("cd /tmp; pwd; pwd -P", "/tmp\n/tmp\n");
+    let dir = common::TempDir::new("pwd");
+    assert_oursh!(format!("ln -s / {0}; cd {0}; pwd -P", dir.join("root")), "/\n");
+}
+
+#[test]
+fn builtin_read() {
+    let dir = common::TempDir::new("read");
+    let file = dir.join("file");
+    assert_oursh!(format!("printf 'a b\\nc\\\\\\nd\\n' > {0}; \
+                           while read -r line; do echo \"[$line]\"; done < {0}", file),
+                  "[a b]\n[c\\]\n[d]\n");
+    assert_oursh!(format!("while read -r line; do\n  echo \"[$line]\"\ndone < {}\n", file),
+                  "[a b]\n[c\\]\n[d]\n");
+    assert_oursh!(format!("read x y < {}; echo $y $x", file), "b a\n");
+    assert_oursh!(format!("{{ read x; read y; }} < {}; echo $y", file), "cd\n");
+    assert_oursh!(format!("echo '  a:b :c ' > {0}; \
+                           IFS=': ' read x y < {0}; echo \"$x|$y\"", file),
+                  "a|b :c\n");
+    assert_oursh!(format!("read < {}; echo \"$REPLY\"", file), "a:b :c\n");
+    assert_oursh!(format!("read -n 3 -d : x < {}; echo $x", file), "a\n");
+    assert_oursh!(format!("read -n 3 x < {}; echo $x", file), "a\n");
+    assert_oursh!(! "read x < /dev/null");
+    assert_oursh!(! format!("read 1x < {}", file));
+}
+
+#[test]
+fn builtin_alias() {
+    assert_oursh!("alias say='echo hi'; eval say there", "hi there\n");
+    assert_oursh!("alias say='echo hi'; alias say; alias", "say='echo hi'\nsay='echo hi'\n");
+    assert_oursh!("alias e='echo ' w=world; eval e w; unalias w; eval e w", "world\nw\n");
+    assert_oursh!("alias echo='echo x'; eval echo", "x\n");
+    assert_oursh!("alias a=b b=a; unalias -a; alias", "");
+    assert_oursh!(! "alias nope");
+    assert_oursh!(! "unalias nope");
+}
+
+#[test]
+fn builtin_type() {
+    assert_oursh!("type cd exit \"if\"",
+                  "cd is a shell builtin\nexit is a special shell builtin\nif is a reserved word\n");
+    assert_oursh!("alias ll='ls -l'; type ll", "ll is an alias for ls -l\n");
+    assert_oursh!("type /bin/sh", "/bin/sh is /bin/sh\n");
+    assert_oursh!(! "type does-not-exist");
+}
+
+#[test]
+fn builtin_command() {
+    assert_oursh!("command echo 1", "1\n");
+    assert_oursh!("command -p sh -c 'echo 1'", "1\n");
+    assert_oursh!("alias ll='ls -l'; command -v ll cd", "alias ll='ls -l'\ncd\n");
+    assert_oursh!("command -V cd", "cd is a shell builtin\n");
+    assert_oursh!("PATH=/bin; command -v sh", "/bin/sh\n");
+    assert_oursh!(! "command -v does-not-exist");
+    // Errors of special builtins only fail the command.
+    assert_oursh!("command set -o bogus; echo $?", "2\n", "oursh: set: bogus: invalid option name\n");
+    assert_oursh!("command shift 5 2> /dev/null || echo 1", "1\n", "");
+    assert_oursh!("command -x 2> /dev/null; type nowhere 2> /dev/null; echo $?", "1\n", "");
+}
+
+#[test]
+fn builtin_hash() {
+    assert_oursh!("PATH=/bin; hash; sh -c true; hash; hash -r; hash", "/bin/sh\n");
+    assert_oursh!("PATH=/bin; hash sh cd; hash", "/bin/sh\n");
+    assert_oursh!(! "hash does-not-exist");
+}
+
+#[test]
+fn builtin_getopts() {
+    assert_oursh!("set -- -ab -c x y; \
+                   while getopts abc: opt; do echo $opt $OPTARG; done; echo $OPTIND",
+                  "a\nb\nc x\n4\n");
+    assert_oursh!("while getopts a:b opt -bax; do echo $opt $OPTARG; done",
+                  "b\na x\n");
+    assert_oursh!("getopts a opt -x; echo $opt; OPTIND=1; getopts a opt -a; echo $opt",
+                  "?\na\n", "oursh: illegal option -- x\n");
+    assert_oursh!("getopts :a: opt -x; echo $opt $OPTARG; \
+                   OPTIND=1; getopts :a: opt -a; echo $opt $OPTARG",
+                  "? x\n: a\n", "");
+    assert_oursh!(! "getopts a opt");
+}
+
+#[test]
+fn builtin_umask() {
+    assert_oursh!("umask 027; umask; umask -S", "0027\nu=rwx,g=rx,o=\n");
+    assert_oursh!("umask 077; umask g+rx,o=r; umask", "0023\n");
+    let dir = common::TempDir::new("umask");
+    assert_oursh!(format!("umask 077; echo > {0}; test -r {0} -a ! -x {0} && echo ok",
+                          dir.join("file")),
+                  "ok\n");
+    assert_oursh!(! "umask 999");
+}
+
+#[test]
+fn builtin_ulimit() {
+    assert_oursh!("ulimit -Sn 100; ulimit -n; sh -c 'ulimit -n'", "100\n100\n");
+    assert_oursh!("ulimit -Sc 0; ulimit -c", "0\n");
+    assert_oursh!(! "ulimit -n nope");
+    assert_oursh!(! "ulimit -z");
+}
+
+#[test]
+fn builtin_times() {
+    let output = oursh!("times");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(2, stdout.lines().count());
+    assert!(stdout.split_whitespace().all(|t| t.contains('m') && t.ends_with('s')));
+}
+
+#[test]
+fn builtin_json() {
+    assert_oursh!("json '{\"a\": 1, \"b\": \"x y\", \"c\": [1]}'; echo $a $b $c", "1 x y [1]\n");
+    assert_oursh!("json \"$(echo not json; echo '{\"a\": true}')\"; echo $a", "true\n");
+    assert_oursh!("json -n x '[1, 2]'; echo $x", "[1,2]\n");
+    assert_oursh!(! "json '[1, 2]'");
+    assert_oursh!(! "json 'not json'");
+}
+
+#[test]
+fn builtin_true_false() {
+    assert_oursh!("true");
+    assert_oursh!(! "false");
+}
+
+#[test]
+fn builtin_set() {
+    assert_oursh!(! "set -e; false; echo 1", "");
+    assert_oursh!("set -e; false || echo 1", "1\n");
+    assert_oursh!("set -e; if false; then :; else echo 1; fi", "1\n");
+    assert_oursh!(! "set -u; echo $UNSET_VARIABLE; echo 1", "");
+    assert_oursh!("set -ex; echo $-", "ex\n", "+ echo ex\n");
+    assert_oursh!("set -o pipefail; false | true || echo 1", "1\n");
+    assert_oursh!("set -e; set +e; false; echo 1", "1\n");
+    assert_oursh!(! "set -o nonsense");
+}
+
+#[test]
+fn builtin_set_xtrace() {
+    assert_oursh!("set -x; X=1; echo $X", "1\n", "+ X=1\n+ echo 1\n");
+    assert_oursh!("PS4='$X> '; X=1; set -x; echo 'a b'", "a b\n", "1> echo 'a b'\n");
+    // Each command of a pipeline is written by its own subshell, in any
+    // order.
+    let output = oursh!("set -x; echo 1 | wc -c");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut lines: Vec<_> = stderr.lines().collect();
+    lines.sort();
+    assert_eq!(vec!["+ echo 1", "+ wc -c"], lines);
+
+    let output = oursh!("set -x -o xtracetime; false");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("+ false\n+ false ("));
+    assert!(stderr.ends_with("s, status 1)\n"));
+
+    // Nested commands report themselves before the command running them.
+    let output = oursh!("set -x -o xtracetime; eval false");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<_> = stderr.lines().collect();
+    assert_eq!(4, lines.len());
+    assert!(lines[2].starts_with("+ false ("));
+    assert!(lines[3].starts_with("+ eval false ("));
+}
+
+#[test]
+fn builtin_set_noclobber() {
+    assert_oursh!("set -C; echo 1 > /dev/null && echo 2", "2\n");
+    let dir = common::TempDir::new("noclobber");
+    let file = dir.join("file");
+    assert_oursh!(! format!("echo 1 > {0}; set -C; echo 2 > {0}", file));
+    assert_oursh!(format!("set -C; echo 1 >| {0} && cat {0}", file), "1\n");
 }
 
 #[test]
@@ -122,6 +519,41 @@ fn background_command() {
     // assert_oursh!("sleep 1 & echo 1", "1\n", "[1]\t(\d*)\n");
 }
 
+#[test]
+fn streaming() {
+    // Nothing past the current command is read, so it can read the rest.
+    assert_oursh!("read x\nhello\necho $x\n", "hello\n");
+    assert_oursh!(! "echo 'a\nb' \\\n c\nfalse\n\n", "a\nb c\n");
+    assert_oursh!("alias say='echo hi'\nsay there\n", "hi there\n");
+    assert_oursh!("echo a &&\n\necho b ||\necho c |\nwc -l\n", "a\nb\n");
+    assert_oursh!("if true; then\necho y\nfi\n", "y\n");
+    assert_oursh!("if true; then\nread x\nfi\nhello\necho $x\n", "hello\n");
+    // Syntax errors only stop the commands after them.
+    let output = oursh!("echo 1\necho )\necho 2\n");
+    assert_eq!(Some(2), output.status.code());
+    assert_eq!("1\n", String::from_utf8_lossy(&output.stdout));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("at 12-13"));
+}
+
+#[test]
+fn verbose_streaming() {
+    let output = oursh!("set -v\necho 1; echo 2\necho 3\n");
+    assert_eq!("1\n2\n3\n", String::from_utf8_lossy(&output.stdout));
+    assert_eq!("echo 1; echo 2\necho 3\n", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn posix_flag() {
+    let output = shell!("target/debug/oursh", &["--noprofile", "--posix"],
+                        "echo 1; {#!/bin/sh; echo 2}");
+    assert_eq!(Some(2), output.status.code());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("language blocks"));
+    let output = shell!("target/debug/oursh", &["--noprofile", "--posix"],
+                        "{ echo 1; }");
+    assert_eq!("1\n", String::from_utf8_lossy(&output.stdout));
+}
+
 #[test]
 #[cfg(feature = "shebang-block")]
 fn shebang_block_sh_command() {
@@ -134,16 +566,115 @@ fn shebang_block_sh_command() {
 }"#, "12345");
 }
 
+#[test]
+#[cfg(feature = "shebang-block")]
+fn shebang_block_status() {
+    assert_eq!(Some(3), oursh!("{#!/bin/sh; exit 3}").status.code());
+    assert_oursh!("{#!/bin/sh; exit 3}; echo $?", "3\n");
+    assert_oursh!("{#!/bin/sh; kill -9 $$}; echo $?", "137\n");
+    assert_oursh!("{#!/bin/sh; true} && echo 1", "1\n");
+}
+
+#[test]
+#[cfg(feature = "shebang-block")]
+fn shebang_block_job() {
+    assert_oursh!("{#!/bin/sh; printf 'b\\na\\n'} | sort", "a\nb\n");
+    assert_oursh!("echo a | {#!/bin/sh; tr a b}", "b\n");
+    let dir = common::TempDir::new("block");
+    assert_oursh!(format!("{{#!/bin/sh; echo 1}} > {0}; cat {0}", dir.join("file")), "1\n");
+    assert_oursh!("{#!/bin/sh; exit 2} || echo 1", "1\n");
+    assert_oursh!("{#!sh; echo 1}", "1\n");
+    let output = oursh!("{#!/bin/sh; echo 1} &");
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("[1]\t"));
+}
+
+#[test]
+#[cfg(feature = "shebang-block")]
+fn shebang_block_variables() {
+    assert_oursh!("export X=1; Y=2; {#!/bin/sh; echo \"$X-$Y\"}", "1-\n");
+    assert_oursh!("set -- a 'b c'; {#!/bin/sh \"$@\" d; echo $# \"$2\"}", "3 b c\n");
+    assert_oursh!(! "{#!/bin/sh -e x; false; echo $1}", "");
+    assert_oursh!("X=1; set -o interpolate; {#!/bin/sh; echo '$X' '\\$X'}", "1 $X\n");
+    assert_oursh!("X=1; {#!/bin/sh; echo \"[$X]\"}", "[]\n");
+}
+
+#[test]
+#[cfg(feature = "shebang-block")]
+fn shebang_block_capture() {
+    assert_oursh!("X=$({#!/bin/sh; echo 42}); echo $X", "42\n");
+    assert_oursh!("X=$({#!/bin/sh; exit 3}) || echo $?", "3\n");
+    assert_oursh!("X=$({#!/bin/sh; printf 'a\\0b'}); echo \"$X\"", "ab\n");
+    assert_oursh!("json \"$({#!/bin/sh; echo '{\"n\": 42}'})\"; echo $n", "42\n");
+}
+
+#[test]
+#[cfg(feature = "shebang-block")]
+fn shebang_block_braces() {
+    assert_oursh!("{#!/bin/sh; f() { echo {1}; }; f}", "{1}\n");
+    assert_oursh!("{#!/bin/sh <<END\necho '}'\nEND}", "}\n");
+}
+
+#[test]
+#[cfg(feature = "shebang-block")]
+fn builtin_lang() {
+    assert_oursh!("lang awk awk -f; echo 1 2 | {#awk {print $2}}", "2\n");
+    assert_oursh!("lang -i up tr a-z A-Z; {#up hello\n}", "HELLO\n");
+    assert_oursh!("lang -e txt ext sh -c 'echo ${0##*.}' '{}'; {#ext x}", "txt\n");
+    assert_oursh!("lang -e txt -i x a '{}'; lang x", "lang -e txt -i x a '{}'\n");
+    assert_oursh!("lang ruby", "lang ruby ruby\n");
+    assert_oursh!(! "lang -u ruby; {#ruby puts 1}");
+    assert_oursh!(! "lang nonsense");
+}
+
+#[test]
+#[cfg(feature = "shebang-block")]
+fn shebang_block_in_process() {
+    assert_oursh!("X=1; {#posix echo $X; Y=2}; echo $Y", "1\n2\n");
+    assert_oursh!("{#posix false} || echo 1", "1\n");
+    assert_oursh!("{#posix echo 1} | wc -l", "1\n");
+    assert_oursh!("{# echo 1}", "1\n");
+    assert_oursh!("{# X=; while [ \"$X\" != aa ]; do X=a$X; echo $X; done | wc -l}", "2\n");
+    assert_oursh!("{# x=1}; echo $x", "1\n");
+}
+
+#[test]
+#[cfg(feature = "shebang-block")]
+fn shebang_block_session() {
+    assert_oursh!("{#python@a x = 1}; {#python@a x += 1}; {#python@a print(x)}", "2\n");
+    assert_oursh!("{#python@a print(1)}; {#python@b print(2)}", "1\n2\n");
+    assert_oursh!("{#python@a print('a\\nb')} | wc -l", "2\n");
+    assert_oursh!("{#python@a import sys; sys.exit(3)} || echo $?", "3\n");
+    assert_oursh!(! "{#python@a x = 1}; {#python@b print(x)}");
+    assert_oursh!("{#python@a import os; os._exit(0)}; {#python@a print(1)}", "1\n");
+    assert_oursh!(! "{#awk@a x}");
+    // Writing to descriptor 1 directly doesn't get in the way of the replies.
+    assert_oursh!("{#python@a import os; os.system('echo 1')}; {#python@a print(2)}",
+                  "1\n2\n");
+    // The interpreter's own options are kept.
+    assert_oursh!("lang py python3 -O '{}'; {#py@a print(__debug__)}", "False\n");
+}
+
 #[test]
 #[cfg(feature = "shebang-block")]
 fn shebang_block_ruby_command() {
     assert_oursh!("{#!/usr/bin/env ruby; puts 1}", "1\n");
+    assert_oursh!("{#!/usr/bin/env ruby; h = {a: {b: 1}}; puts h[:a][:b]}", "1\n");
+    assert_oursh!("{#ruby <<END puts \"}\" END}", "}\n");
+}
+
+#[test]
+#[cfg(feature = "shebang-block")]
+fn shebang_block_node_command() {
+    assert_oursh!("{#!/usr/bin/env node; const o = {a: {b: 1}}; console.log(o.a.b)}", "1\n");
+    assert_oursh!("{#node <<END console.log(`${'}'}`) END}", "}\n");
 }
 
 #[test]
 #[cfg(feature = "shebang-block")]
 fn shebang_block_python_command() {
     assert_oursh!("{#!/usr/bin/env python; print(1)}", "1\n");
+    assert_oursh!("{#!/usr/bin/env python; d = {'a': {'b': 1}}; print(d['a']['b'])}", "1\n");
+    assert_oursh!("{#python <<END print('}') END}", "}\n");
     assert_oursh!("{#!/usr/bin/env python  ;    print(1)}", "1\n");
     assert_oursh!(r#"{#!/usr/bin/env python;
 print("hello world")
@@ -168,3 +699,36 @@ fn shebang_block_rust_command() {
     }
 }"#, "hello world!\n");
 }
+
+#[test]
+#[cfg(feature = "shebang-block")]
+fn modern_language() {
+    assert_oursh!("{#modern for i in (0..3) { echo $i }}", "0\n1\n2\n");
+    assert_oursh!("{#modern for i in (1..=2) { echo $i }}", "1\n2\n");
+    assert_oursh!("{#modern for i in a b { echo $i }}", "a\nb\n");
+    assert_oursh!("{#modern fn greet(a, b) { echo $b $a }; greet x y}", "y x\n");
+    assert_oursh!("{#modern if false { echo 1 } else if true { echo 2 } else { echo 3 }}",
+                  "2\n");
+    assert_oursh!("{#modern let x = 'a  b'; let y = $x; echo \"$y\"}", "a  b\n");
+    assert_oursh!("{#modern let n = 0; while test $n != 000 { let n = 0$n; echo $n }}",
+                  "00\n000\n");
+    assert_oursh!("{#modern echo 1 | wc -l && ! false || echo 2}", "1\n");
+    assert_oursh!("{#modern {#posix X=1}}; echo $X", "1\n");
+    assert_oursh!(! "{#modern if true {", "");
+    assert_oursh!(! "{#modern echo 1 >&2}", "");
+}
+
+#[test]
+#[cfg(feature = "shebang-block")]
+fn modern_functions() {
+    assert_oursh!("{#modern fn f() { echo 1 }}; type f; command -v f", "f is a function\nf\n");
+    assert_oursh!("{#modern fn echo(x) { command echo [$x] }; echo 1; command echo 2}",
+                  "[1]\n2\n");
+}
+
+#[test]
+fn modern_flag() {
+    let output = shell!("target/debug/oursh", &["--noprofile", "--modern"],
+                        "for i in (0..2) {\n    echo $i\n}\n");
+    assert_eq!("0\n1\n", String::from_utf8_lossy(&output.stdout));
+}
@@ -16,6 +16,15 @@ fn multiline() {
     assert_oursh!(> "./scripts/multiline.sh", "12\n");
 }
 
+#[test]
+fn args() {
+    let output = shell!("target/debug/oursh",
+                        &["--noprofile", "./scripts/args.sh", "a", "-b", "c d"],
+                        "");
+    assert_eq!("./scripts/args.sh 3 a -b c d\n2 -b\n0 \n",
+               String::from_utf8_lossy(&output.stdout));
+}
+
 #[test]
 #[cfg(feature = "shebang-block")]
 fn date() {